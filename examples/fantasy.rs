@@ -1,8 +1,10 @@
-use bevy::ecs::query;
-use bevy::{asset::ron, prelude::*};
+use bevy::picking::pointer::PointerButton;
+use bevy::prelude::*;
+use bevy::ui::ComputedUiRenderTargetInfo;
 use bevy_pkv::{PersistentResourceAppExtensions, PkvStore};
+use siminv::input::modified_click::{ClickBinding, ClickBindings, Modifiers};
 use siminv::prelude::*;
-use siminv::simple_renderer::{SiminvSimpleRendererPlugin, SimpleImageHandle, SimpleRendererAssets};
+use siminv::simple_renderer::{popup_position, SiminvSimpleRendererPlugin, SimpleImageHandle, SimpleRendererAssets, WeightText};
 use bevy_asset_loader::prelude::*;
 
 const BACKGROUND_COLOR: Color = Color::srgb(0.533, 0.584, 0.624);
@@ -30,6 +32,10 @@ struct GameAssets {
     #[asset(image(sampler(filter = nearest)))]
     #[asset(path = "images/icons.png")]
     icons: Handle<Image>,
+
+    // registered in `ItemTypes` by the SiminvPlugin as soon as it is loaded
+    #[asset(path = "data/fantasy.items.ron")]
+    _item_types: Handle<ItemTypeDatabase>,
 }
 
 impl GameAssets {
@@ -71,30 +77,41 @@ impl SimpleRendererAssets for GameAssets {
     }
 }
 
-// load defaults here, cause they depend on each other
-// TODO: make make them not depend on each other
-fn default_resources() -> (Items, Inventory) {
-    const ITEMS_RON: &str = include_str!("../assets/data/fantasy.ron");
-    let item_types: Vec<ItemType> = ron::from_str(ITEMS_RON).expect("Failed to parse item_types.ron");
-    let mut items = Items::default();
-    items.register_item_types(item_types);
-
-    let mut inventory = Inventory::default();
-    inventory.set_max_size("stash".into(), UVec2::new(5, 8));
-    inventory.set_max_size("backpack".into(), UVec2::new(5, 4));
-    inventory.set_max_size("equipment".into(), UVec2::new(3, 4));
-
-    inventory.add("backpack", items.add_item("shield"));
-    inventory.add("backpack", items.add_item("sword"));
-    inventory.add("backpack", items.add_item("sword"));
-    inventory.add("backpack", items.add_item("helmet"));
-    inventory.add("backpack", items.add_item("bow"));
-    inventory.add("backpack", items.add_item("armor"));
-    inventory.add("backpack", items.add_items("stones", 5));
-    inventory.add("backpack", items.add_items("stones", 10));
-    inventory.add("backpack", items.add_items("stones", 17));
-
-    (items, inventory)
+// runs once item types are loaded, cause items can only be created for registered item types
+fn add_default_items(
+    mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    validators: Res<MoveValidators>,
+    mut inventory: ResMut<Inventory>,
+) {
+    inventory.set_max_size("stash", UVec2::new(5, 8));
+    inventory.set_max_size("backpack", UVec2::new(5, 4));
+    inventory.set_max_size("equipment", UVec2::new(3, 4));
+    inventory.set_max_weight("backpack", Some(50.));
+
+    // items were loaded from the persistent storage
+    if !inventory.is_empty() {
+        return
+    }
+
+    info!("setting default items");
+    let default_items = [
+        ("shield", 1),
+        ("sword", 1),
+        ("sword", 1),
+        ("helmet", 1),
+        ("bow", 1),
+        ("armor", 1),
+        ("stones", 5),
+        ("stones", 10),
+        ("stones", 17),
+    ];
+
+    for (type_name, count) in default_items {
+        for item in items.add_items(type_name, count, &item_types).expect("item types to be loaded") {
+            inventory.add("backpack", item, &items, &item_types, &validators).expect("default items to fit");
+        }
+    }
 }
 
 fn main() {
@@ -116,37 +133,47 @@ fn main() {
                 .load_collection::<GameAssets>()
                 .continue_to_state(GameState::Next)
         )
-        .insert_resource(PkvStore::new("siminv", "example.06"))
-        .init_persistent_resource_with(move || {
-            println!("setting default items");
-            default_resources().0
-        })
-        .init_persistent_resource_with(move || {
-            println!("setting default inventory");
-            default_resources().1
-        })
+        .insert_resource(PkvStore::new("siminv", "example.07"))
+        .init_persistent_resource_with(Items::default)
+        .init_persistent_resource_with(Inventory::default)
 
         .insert_resource(UiScale(1.0))
-        .add_plugins(SiminvPlugin)
+        .add_plugins(SiminvPlugin {
+            ask_split_amount: true,
+            ..default()
+        })
         .add_plugins(SiminvSimpleRendererPlugin::<GameAssets, FantasyStyle>::default())
         
         // backpack
-        .add_observer(on_event_move_to::<SlotDoubleClick, Backpack, Equipment, { MovePolicy::EMPTY_OR_REPLACE }>)
-        .add_observer(on_event_move_to::<SlotShiftClick, Backpack, Stash, { MovePolicy::ONLY_EMPTY }>)
+        .insert_resource(AutoMovePolicy::<Backpack, Equipment>::new(SwapWithSameTag))
+        .insert_resource(AutoMovePolicy::<Backpack, Stash>::new(MergeFirst))
+        .add_observer(on_event_move_to::<SlotDoubleClick, Backpack, Equipment>)
+        .add_observer(on_event_move_to::<SlotShiftClick, Backpack, Stash>)
+        .add_observer(on_event_move_to::<EquipClick, Backpack, Equipment>)
 
         // equipment
-        .add_observer(on_event_move_to::<SlotDoubleClick, Equipment, Backpack, { MovePolicy::ONLY_EMPTY }>)
-        .add_observer(on_event_move_to::<SlotShiftClick, Equipment, Stash, { MovePolicy::ONLY_EMPTY }>)
+        .insert_resource(AutoMovePolicy::<Equipment, Backpack>::new(MergeFirst))
+        .insert_resource(AutoMovePolicy::<Equipment, Stash>::new(MergeFirst))
+        .add_observer(on_event_move_to::<SlotDoubleClick, Equipment, Backpack>)
+        .add_observer(on_event_move_to::<SlotShiftClick, Equipment, Stash>)
         
         // stash
-        .add_observer(on_event_move_to::<SlotDoubleClick, Stash, Equipment, { MovePolicy::EMPTY_OR_REPLACE }>)
-        .add_observer(on_event_move_to::<SlotShiftClick, Stash, Backpack, { MovePolicy::ONLY_EMPTY }>)
+        .insert_resource(AutoMovePolicy::<Stash, Equipment>::new(SwapWithSameTag))
+        .insert_resource(AutoMovePolicy::<Stash, Backpack>::new(MergeFirst))
+        .add_observer(on_event_move_to::<SlotDoubleClick, Stash, Equipment>)
+        .add_observer(on_event_move_to::<SlotShiftClick, Stash, Backpack>)
+        .add_observer(on_event_move_to::<EquipClick, Stash, Equipment>)
 
-        .add_systems(OnEnter(GameState::Next), setup)
+        .add_systems(OnEnter(GameState::Next), (add_default_items, setup).chain())
 		.add_systems(Update, update_ui_scale)
+        .add_systems(Update, undo_redo)
         .add_observer(on_button_press)
+        .add_observer(on_sort_press)
         .add_observer(on_slot_hover)
         .add_observer(on_slot_hover_over)
+        .add_observer(on_slot_error)
+        .add_observer(on_context_action)
+        .add_systems(Startup, (setup_context_menus, setup_click_bindings))
         .run();
 }
 
@@ -154,6 +181,10 @@ fn main() {
 #[derive(Component, Default)]
 struct FantasyStyle;
 
+/// Alt-click, equipping the item. Ctrl-drags split stacks.
+#[derive(Clone)]
+struct EquipClick;
+
 /// Marker for equipment. Used to track click events.
 #[derive(Component, Default)]
 struct Equipment;
@@ -176,6 +207,25 @@ fn update_ui_scale(
     }
 }
 
+// ctrl+z and ctrl+y
+fn undo_redo(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<History>,
+    mut inventory: ResMut<Inventory>,
+    mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return
+    }
+
+    if keys.just_pressed(KeyCode::KeyZ) {
+        history.undo(&mut inventory, &mut items, &item_types);
+    } else if keys.just_pressed(KeyCode::KeyY) {
+        history.redo(&mut inventory, &mut items, &item_types);
+    }
+}
+
 fn setup(
     mut commands: Commands,
 ) {
@@ -214,6 +264,7 @@ fn setup(
             justify_self: JustifySelf::Start,
             align_items: AlignItems::Center,
             justify_content: JustifyContent::Center,
+            flex_direction: FlexDirection::Column,
             width: percent(35),
             height: percent(50),
             ..default()
@@ -224,7 +275,9 @@ fn setup(
                 columns: 5, 
                 rows: 4,
                 ..default()
-            })
+            }),
+            // encumbrance of the backpack
+            (WeightText { collection: "backpack".into() }, FantasyStyle),
         ]
     ));
 
@@ -249,22 +302,21 @@ fn setup(
                 columns: 3, 
                 rows: 4,
                 required_tags: [
-                    (UVec2::new(1, 0), Tag("helmet".into())),
-                    (UVec2::new(2, 0), Tag("neckles".into())),
-                    (UVec2::new(0, 1), Tag("weapon".into())),
-                    (UVec2::new(1, 1), Tag("armor".into())),
-                    (UVec2::new(2, 1), Tag("off-hand".into())),
-                    (UVec2::new(0, 2), Tag("ring".into())),
-                    (UVec2::new(2, 2), Tag("ring".into())),
-                    (UVec2::new(0, 3), Tag("gloves".into())),
-                    (UVec2::new(1, 3), Tag("boots".into())),
+                    (UVec2::new(1, 0), TagRule::from(Tag("helmet".into()))),
+                    (UVec2::new(2, 0), TagRule::AnyOf(vec![Tag("neckles".into()), Tag("amulet".into())])),
+                    (UVec2::new(0, 1), TagRule::from(Tag("weapon".into()))),
+                    (UVec2::new(1, 1), TagRule::from(Tag("armor".into()))),
+                    (UVec2::new(2, 1), TagRule::from(Tag("off-hand".into()))),
+                    (UVec2::new(0, 2), TagRule::from(Tag("ring".into()))),
+                    (UVec2::new(2, 2), TagRule::from(Tag("ring".into()))),
+                    (UVec2::new(0, 3), TagRule::from(Tag("gloves".into()))),
+                    (UVec2::new(1, 3), TagRule::from(Tag("boots".into()))),
                 ].into(),
                 blocked_indexes: vec![
                     UVec2::new(0, 0),
                     UVec2::new(1, 2),
                     UVec2::new(2, 3),
                 ].into_iter().collect(),
-                ..default()
             })
         ]
     ));
@@ -300,99 +352,61 @@ fn setup(
         Text::new("Get Sword"),
         AddButton,
     ));
+
+    commands.spawn((
+        Node {
+            align_self: AlignSelf::Center,
+            justify_self: JustifySelf::Center,
+            top: px(80),
+            width: px(120),
+            height: px(80),
+            ..default()
+        },
+        Text::new("Sort Stash"),
+        SortButton,
+    ));
 }
 
 #[derive(Component)]
 struct AddButton;
 
+#[derive(Component)]
+struct SortButton;
+
 fn on_button_press(
     clicked: On<Pointer<Click>>,
     query: Query<&AddButton>,
     mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    validators: Res<MoveValidators>,
     mut inventory: ResMut<Inventory>,
 ) {
     if !query.contains(clicked.entity) {
         return
     }
 
-    inventory.add("stash", items.add_item("sword"));
-}
+    let result = items.add_item("sword", &item_types)
+        .and_then(|sword| inventory.add("stash", sword, &items, &item_types, &validators));
 
-enum ScreenPart {
-    TopLeft,
-    TopRight,
-    BottomRight,
-    BottomLeft,
-}
-
-impl ScreenPart {
-    fn corner(&self) -> Vec2 {
-        match *self {
-            ScreenPart::TopLeft => Vec2::new(-1., -1.),
-            ScreenPart::TopRight => Vec2::new(1., -1.),
-            ScreenPart::BottomRight => Vec2::new(1., 1.),
-            ScreenPart::BottomLeft => Vec2::new(-1., 1.),
-        }
-    }
-
-    fn opposite(&self) -> Self {
-        match *self {
-            ScreenPart::TopLeft => ScreenPart::BottomRight,
-            ScreenPart::BottomRight => ScreenPart::TopLeft,
-            ScreenPart::TopRight => ScreenPart::BottomLeft,
-            ScreenPart::BottomLeft => ScreenPart::TopRight,
-        }
+    if let Err(error) = result {
+        warn!("could not add a sword: {error}");
     }
 }
 
-fn compute_screen_part(transform: &UiGlobalTransform, window: &Window) -> ScreenPart {
-    let center = transform.transform_point2(Vec2::splat(0.));
-
-    match (center.y <= (window.resolution.physical_height() / 2) as f32, center.x <= (window.resolution.physical_width() / 2) as f32) {
-        (true, true) => ScreenPart::TopLeft,
-        (true, false) => ScreenPart::TopRight,
-        (false, true) => ScreenPart::BottomLeft,
-        (false, false) => ScreenPart::BottomRight,
+fn on_sort_press(
+    clicked: On<Pointer<Click>>,
+    query: Query<&SortButton>,
+    mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    validators: Res<MoveValidators>,
+    mut inventory: ResMut<Inventory>,
+) {
+    if !query.contains(clicked.entity) {
+        return
     }
-}
-
-fn tooltip_node_position(node: &ComputedNode, transform: &UiGlobalTransform, tooltip_size: &UVec2, window: &Window) -> UiRect {
-    let screen_part = compute_screen_part(transform, window);
-
-    let half_size = node.size() * 0.5;
-    let corner = half_size * screen_part.opposite().corner();
 
-    let corner_screen_coords = transform.transform_point2(corner) * node.inverse_scale_factor();
-
-    match screen_part {
-        ScreenPart::TopLeft => {
-            UiRect { 
-                top: px(corner_screen_coords.y),
-                left: px(corner_screen_coords.x),
-                ..default()
-            }
-        },
-        ScreenPart::BottomLeft => {
-            UiRect {
-                top: px(corner_screen_coords.y - tooltip_size.y as f32),
-                left: px(corner_screen_coords.x),
-                ..default()
-            }
-        },
-        ScreenPart::TopRight => {
-            UiRect {
-                top: px(corner_screen_coords.y),
-                left: px(corner_screen_coords.x - tooltip_size.x as f32),
-                ..default()
-            }
-        },
-        ScreenPart::BottomRight => {
-            UiRect {
-                top: px(corner_screen_coords.y - tooltip_size.y as f32),
-                left: px(corner_screen_coords.x - tooltip_size.x as f32),
-                ..default()
-            }
-        }
+    if let Err(error) = inventory.sort_collection("stash", SortKey::TypeName, &mut items, &item_types, &validators) {
+        warn!("could not sort the stash: {error}");
     }
 }
 
@@ -402,19 +416,19 @@ struct TooltipMarker;
 fn on_slot_hover(
     hover: On<SlotEvent<SlotHover>>,
     mut commands: Commands,
-    query: Query<(&SlotHandle, &ComputedNode, &UiGlobalTransform)>,
+    query: Query<(&SlotHandle, &ComputedNode, &UiGlobalTransform, &ComputedUiRenderTargetInfo)>,
     query_slot: Query<&Slot>,
-    window: Single<&Window>,
     items: Res<Items>,
+    item_types: Res<ItemTypes>,
 ) {
-    println!("hover");
+    debug!("hover");
     
-    let Ok((slot_handle, node, transform)) = query.get(hover.entity) else { return };
+    let Ok((slot_handle, node, transform, target)) = query.get(hover.entity) else { return };
     let Ok(slot) = query_slot.get(slot_handle.0) else { return };
-    let Some(item) = slot.item.and_then(|item_id| items.get_item_meta(item_id)) else { return };
+    let Some(item) = slot.item.and_then(|item_id| items.get_item_meta(item_id, &item_types)) else { return };
 
     let tooltip_size = UVec2::new(160, 120);
-    let position = tooltip_node_position(node, transform, &tooltip_size, &window);
+    let position = popup_position(node, transform, target, &tooltip_size);
 
     commands.spawn((
         Node {
@@ -439,14 +453,68 @@ fn on_slot_hover(
 }
 
 fn on_slot_hover_over(
-    over: On<SlotEvent<SlotHoverOver>>,
+    _over: On<SlotEvent<SlotHoverOver>>,
     mut commands: Commands,
     query: Query<Entity, With<TooltipMarker>>,
 ) {
-    println!("over");
+    debug!("over");
     for tooltip in query {
         // there should be just one :)
         commands.entity(tooltip).despawn();
     }
 }
 
+fn setup_click_bindings(mut click_bindings: ResMut<ClickBindings>) {
+    click_bindings.bind(ClickBinding::new(Modifiers::ALT, PointerButton::Primary), EquipClick);
+}
+
+// actions offered on right click
+fn setup_context_menus(mut context_menus: ResMut<ContextMenus>) {
+    context_menus.add_for_type("stones", [ContextAction::Split, ContextAction::Drop, ContextAction::Destroy]);
+    for tag in ["weapon", "helmet", "armor", "off-hand"] {
+        context_menus.add_for_tag(Tag(tag.into()), [ContextAction::Equip, ContextAction::Drop]);
+    }
+}
+
+fn on_context_action(
+    action: On<SlotEvent<SlotContextAction>>,
+    query: Query<&InventoryHandle>,
+    mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    validators: Res<MoveValidators>,
+    mut inventory: ResMut<Inventory>,
+) {
+    let Ok(handle) = query.get(action.entity) else { return };
+
+    let result = match &action.action {
+        ContextAction::Equip => inventory
+            .quick_move(&handle.collection, handle.index, "equipment", &SwapWithSameTag, &mut items, &item_types, &validators)
+            .map(|_| ()),
+        // half of the stack goes to the first free index
+        ContextAction::Split => {
+            let stack_size = items.get_item(action.item).map_or(0, |item| item.stack_size);
+            match inventory.first_free_index(&handle.collection) {
+                Some(index) => inventory.split(&handle.collection, handle.index, &handle.collection, index, stack_size / 2, &mut items, &item_types, &validators),
+                None => Err(SiminvError::CollectionFull(handle.collection.clone())),
+            }
+        },
+        ContextAction::Destroy => inventory
+            .remove(&handle.collection, handle.index)
+            .and_then(|item| items.remove_item(item))
+            .map(|_| ()),
+        other => {
+            info!("{} is not implemented in the example", other.label());
+            Ok(())
+        },
+    };
+
+    if let Err(error) = result {
+        warn!("{error}");
+    }
+}
+
+fn on_slot_error(
+    error: On<SlotEvent<SlotError>>,
+) {
+    warn!("{}", error.error);
+}
@@ -1,34 +1,195 @@
+use std::{collections::BTreeSet, marker::PhantomData};
+
 use bevy::prelude::*;
 
-use crate::{event::{SlotEvent, SlotUpdate, TriggerSlotEvent}, item::Items, slot::{InventoryHandle, Slot}};
+use crate::{error::SiminvError, event::{SlotError, SlotEvent, SlotUpdate, TriggerSlotEvent}, inventory::Inventory, item::{ItemId, ItemTypes, Items}, move_validator::{ItemMove, MoveValidators}, slot::{check_fits_after_swap, InventoryHandle, Slot}};
+
+/// Index the item can be moved to. It is either empty, or occupied by an item
+/// that the moved item can be merged or swapped with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveCandidate {
+    /// Index in the target collection. Slots outside of grids have zero index.
+    pub index: UVec2,
+    pub item: Option<ItemId>,
+}
+
+/// Decides where the auto-moved items go.
+pub trait MovePolicy: Send + Sync + 'static {
+    /// Returns positions in `candidates` that the item should be moved into, in order.
+    /// Candidates are sorted row after row. The item is moved into the targets until
+    /// the whole stack has left the source, so partial stacks can be topped up before
+    /// the rest is moved into an empty index. Returning no targets rejects the move.
+    fn targets(&self, item: ItemId, candidates: &[MoveCandidate], items: &Items, item_types: &ItemTypes) -> Vec<usize>;
+}
+
+fn first_empty(candidates: &[MoveCandidate]) -> Option<usize> {
+    candidates.iter().position(|candidate| candidate.item.is_none())
+}
+
+/// Moves the item into the first empty index.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct OnlyEmpty;
+
+impl MovePolicy for OnlyEmpty {
+    fn targets(&self, _item: ItemId, candidates: &[MoveCandidate], _items: &Items, _item_types: &ItemTypes) -> Vec<usize> {
+        first_empty(candidates).into_iter().collect()
+    }
+}
+
+/// Moves the item into the first empty index, or replaces the first item if there are none.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct EmptyOrReplace;
+
+impl MovePolicy for EmptyOrReplace {
+    fn targets(&self, _item: ItemId, candidates: &[MoveCandidate], _items: &Items, _item_types: &ItemTypes) -> Vec<usize> {
+        first_empty(candidates)
+            .or_else(|| candidates.iter().position(|candidate| candidate.item.is_some()))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Tops up partial stacks of the same type, then moves the rest into the first empty index.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MergeFirst;
+
+impl MovePolicy for MergeFirst {
+    fn targets(&self, item: ItemId, candidates: &[MoveCandidate], items: &Items, item_types: &ItemTypes) -> Vec<usize> {
+        candidates.iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.item.is_some_and(|into| items.can_merge(item, into, item_types)))
+            .map(|(position, _)| position)
+            .chain(first_empty(candidates))
+            .collect()
+    }
+}
+
+/// Moves the item into the first empty index of the last row.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct FillFromBottom;
+
+impl MovePolicy for FillFromBottom {
+    fn targets(&self, _item: ItemId, candidates: &[MoveCandidate], _items: &Items, _item_types: &ItemTypes) -> Vec<usize> {
+        candidates.iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.item.is_none())
+            .min_by_key(|(_, candidate)| (core::cmp::Reverse(candidate.index.y), candidate.index.x))
+            .map(|(position, _)| position)
+            .into_iter()
+            .collect()
+    }
+}
 
-pub struct MovePolicy;
+/// Swaps the item with the first item sharing any of its tags, e.g. equips a sword
+/// in place of the equipped axe. Moves it into the first empty index if there is no such item.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SwapWithSameTag;
 
-impl MovePolicy {
-    pub const ONLY_EMPTY: u8 = 0u8;
-    pub const EMPTY_OR_REPLACE: u8 = 1u8;
+impl MovePolicy for SwapWithSameTag {
+    fn targets(&self, item: ItemId, candidates: &[MoveCandidate], items: &Items, item_types: &ItemTypes) -> Vec<usize> {
+        let Some(tags) = items.get_item_meta(item, item_types).map(|meta| meta.tags) else { return vec![] };
+        candidates.iter()
+            .position(|candidate| {
+                candidate.item
+                    .and_then(|into| items.get_item_meta(into, item_types))
+                    .is_some_and(|into| into.tags.iter().any(|tag| tags.contains(tag)))
+            })
+            .or_else(|| first_empty(candidates))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Move policy used by `on_event_move_to` for items moved from slots marked with F
+/// into slots marked with T. It can be replaced at any time, e.g. by game settings.
+#[derive(Resource)]
+pub struct AutoMovePolicy<F, T> {
+    policy: Box<dyn MovePolicy>,
+    marker: PhantomData<fn() -> (F, T)>,
+}
+
+impl<F: Component, T: Component> AutoMovePolicy<F, T> {
+    pub fn new(policy: impl MovePolicy) -> Self {
+        AutoMovePolicy {
+            policy: Box::new(policy),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn set(&mut self, policy: impl MovePolicy) {
+        self.policy = Box::new(policy);
+    }
+
+    pub fn policy(&self) -> &dyn MovePolicy {
+        self.policy.as_ref()
+    }
 }
 
 // SlotEvent can be double-click, cmd-click, ctrl-click, shift-click etc.
 /// This observer function is used to move Items from collection F, to collection T, when
-/// event E is triggered.
+/// event E is triggered. The target is decided by the `AutoMovePolicy<F, T>` resource,
+/// which has to be inserted.
+/// Slots displaying the inventory are handled by `Inventory::quick_move`. If the T slots
+/// display several collections, they are tried in the order of their names.
 // on_event_move_from_to
-pub fn on_event_move_to<E: Send + Sync + 'static, F: Component, T: Component, const R: u8> (
+#[allow(clippy::too_many_arguments)]
+pub fn on_event_move_to<E: Send + Sync + 'static, F: Component, T: Component> (
     event: On<SlotEvent<E>, F>,
     mut commands: Commands,
-    mut query_from: Query<&mut Slot, Without<T>>,
-    query_into: Query<(Entity, &mut Slot, Option<&InventoryHandle>), With<T>>,
-    items: Res<Items>,
+    mut query_from: Query<(&mut Slot, Option<&InventoryHandle>), Without<T>>,
+    mut query_into: Query<(Entity, &mut Slot, Option<&InventoryHandle>), With<T>>,
+    mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    mut inventory: ResMut<Inventory>,
+    policy: Res<AutoMovePolicy<F, T>>,
+    validators: Res<MoveValidators>,
 ) {
     // slot that triggered the event
-    let Ok(mut from_slot) = query_from.get_mut(event.entity) else { return };
+    let Ok((mut from_slot, from_handle)) = query_from.get_mut(event.entity) else { return };
     // if there is no item in the slot, ignore event
     let Some(from_item_id) = from_slot.item else { return };
-    // this should throw? panic? if not present? 
-    let Some(from_item) = items.get_item_meta(from_item_id) else { return };
-    
-    // TODO:
-    let stackable = from_item.max_stack_size != 1;
+    let policy = policy.policy();
+
+    // if both sides display the inventory, let the inventory move the item
+    let into_collections = query_into.iter()
+        .filter_map(|(_, _, handle)| handle.map(|handle| handle.collection.clone()))
+        .collect::<BTreeSet<_>>();
+    if let Some(from_handle) = from_handle.filter(|_| !into_collections.is_empty()) {
+        let mut result = Err(SiminvError::NoMatchingSlot(from_item_id));
+        for into_collection in &into_collections {
+            result = inventory.quick_move(&from_handle.collection, from_handle.index, into_collection, policy, &mut items, &item_types, &validators)
+                .map(|into_indexes| (into_collection, into_indexes));
+            if result.is_ok() {
+                break
+            }
+        }
+
+        match result {
+            Ok((into_collection, into_indexes)) => {
+                // keep the slots in sync, so SlotUpdate does not write the old items back
+                from_slot.item = inventory.get(&from_handle.collection, &from_handle.index).copied();
+                commands.trigger_slot_event(SlotEvent::new(event.entity, SlotUpdate));
+                for (into_entity, mut into_slot, handle) in query_into.iter_mut() {
+                    let Some(handle) = handle.filter(|handle| handle.collection == *into_collection && into_indexes.contains(&handle.index)) else { continue };
+                    into_slot.item = inventory.get(&handle.collection, &handle.index).copied();
+                    commands.trigger_slot_event(SlotEvent::new(into_entity, SlotUpdate));
+                }
+            },
+            Err(error) => {
+                commands.trigger_slot_event(SlotEvent::new(event.entity, SlotError { error }));
+            },
+        }
+        return
+    }
+
+    // otherwise move the item between the slots
+    let from_tags = match items.try_get_item_meta(from_item_id, &item_types) {
+        Ok(from_item) => from_item.tags.to_vec(),
+        Err(error) => {
+            commands.trigger_slot_event(SlotEvent::new(event.entity, SlotError { error }));
+            return
+        },
+    };
 
     // lets order the slots row after row
     let mut ordered_into_slots = query_into
@@ -45,37 +206,100 @@ pub fn on_event_move_to<E: Send + Sync + 'static, F: Component, T: Component, co
                 a.y.cmp(&b.y)
             }
         })
-        .map(|(e, slot, _)| (e, slot))
         .collect::<Vec<_>>();
 
-    let empty_into_slot = ordered_into_slots
-        .iter_mut()
-        .find(|(_, slot)| {
-            slot.is_empty() && slot.matching_tag(from_item.tags) 
-        });
-
-    match empty_into_slot {
-        Some((into_entity, into_slot)) => {
-            // TODO: use more sophisticated methods, like merge && swap 
-            core::mem::swap(&mut from_slot.item, &mut into_slot.item);
-            commands.trigger_slot_event(SlotEvent::new(event.entity, SlotUpdate));
-            commands.trigger_slot_event(SlotEvent::new(*into_entity, SlotUpdate));
-        },
-        None => {
-            // if there are no empty slots matching the tag, maybe replace the existing slot?
-            if R == MovePolicy::EMPTY_OR_REPLACE {
-                let Some((into_entity, into_slot)) = ordered_into_slots
-                    .iter_mut()
-                    .find(|(_, slot)| slot.matching_tag(from_item.tags))
-                    // if there are no matching slots, just return
-                    else {
-                        return
-                    };
-                core::mem::swap(&mut from_slot.item, &mut into_slot.item);
-                commands.trigger_slot_event(SlotEvent::new(event.entity, SlotUpdate));
-                commands.trigger_slot_event(SlotEvent::new(*into_entity, SlotUpdate));
-            }
-        },
+    // the item must match the tag and must not overlap other items, nor leave the collection
+    // the replaced item must match the tag of the source slot
+    // and the game rules must allow the move
+    let accepts = |slot: &Slot, handle: Option<&InventoryHandle>| {
+        slot.matching_tag(&from_tags)
+            && slot.item.is_none_or(|into| items.get_item_meta(into, &item_types).is_none_or(|into| from_slot.matching_tag(into.tags)))
+            && check_fits_after_swap(&inventory, &items, &item_types, (from_handle, from_slot.item), (handle, slot.item)).is_ok()
+            && validators.check(&ItemMove {
+                item: from_item_id,
+                from: from_handle.cloned(),
+                to: handle.cloned(),
+                replaced: slot.item,
+            }, &inventory, &items, &item_types).is_ok()
+    };
+
+    let (positions, candidates): (Vec<usize>, Vec<MoveCandidate>) = ordered_into_slots
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, slot, handle))| accepts(slot, *handle))
+        .map(|(position, (_, slot, handle))| (position, MoveCandidate {
+            index: handle.map(|handle| handle.index).unwrap_or_default(),
+            item: slot.item,
+        }))
+        .unzip();
+
+    let targets = policy.targets(from_item_id, &candidates, &items, &item_types);
+    // if there are no matching slots, report it
+    if targets.is_empty() {
+        let error = SiminvError::NoMatchingSlot(from_item_id);
+        commands.trigger_slot_event(SlotEvent::new(event.entity, SlotError { error }));
+        return
     }
+
+    for target in targets {
+        let Some(position) = positions.get(target) else { continue };
+        let (into_entity, into_slot, _) = &mut ordered_into_slots[*position];
+        let Some(from_id) = from_slot.item else { break };
+
+        match into_slot.item {
+            // merge or swap them
+            Some(into_id) => match items.merge_or_swap(from_id, into_id, &item_types) {
+                Ok((new_from, new_into)) => {
+                    from_slot.item = new_from;
+                    into_slot.item = new_into;
+                },
+                Err(error) => {
+                    commands.trigger_slot_event(SlotEvent::new(event.entity, SlotError { error }));
+                    break
+                },
+            },
+            // move slot item onto empty space
+            None => core::mem::swap(&mut from_slot.item, &mut into_slot.item),
+        }
+        commands.trigger_slot_event(SlotEvent::new(*into_entity, SlotUpdate));
+
+        // the whole stack has left the source
+        if from_slot.item != Some(from_item_id) {
+            break
+        }
+    }
+    commands.trigger_slot_event(SlotEvent::new(event.entity, SlotUpdate));
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::item::{ItemType, Tag};
+    use super::*;
+
+    #[test]
+    fn test_move_policies() {
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "sword".into(), display_name: "Sword".into(), max_stack_size: 1, tags: vec![Tag("weapon".into())], ..default() },
+            ItemType { type_name: "axe".into(), display_name: "Axe".into(), max_stack_size: 1, tags: vec![Tag("weapon".into())], ..default() },
+            ItemType { type_name: "ring".into(), display_name: "Ring".into(), max_stack_size: 1, tags: vec![Tag("ring".into())], ..default() },
+        ]);
+        let mut items = Items::default();
+        let sword = items.add_item("sword", &item_types).unwrap();
+        let axe = items.add_item("axe", &item_types).unwrap();
+        let ring = items.add_item("ring", &item_types).unwrap();
+
+        let candidates = [
+            MoveCandidate { index: UVec2::new(0, 0), item: Some(ring) },
+            MoveCandidate { index: UVec2::new(1, 0), item: None },
+            MoveCandidate { index: UVec2::new(0, 1), item: Some(axe) },
+            MoveCandidate { index: UVec2::new(1, 1), item: None },
+        ];
+
+        assert_eq!(OnlyEmpty.targets(sword, &candidates, &items, &item_types), vec![1]);
+        assert_eq!(EmptyOrReplace.targets(sword, &candidates[2..3], &items, &item_types), vec![0]);
+        assert_eq!(FillFromBottom.targets(sword, &candidates, &items, &item_types), vec![3]);
+        assert_eq!(SwapWithSameTag.targets(sword, &candidates, &items, &item_types), vec![2]);
+        assert_eq!(OnlyEmpty.targets(sword, &candidates[..1], &items, &item_types), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,95 @@
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::item::{ItemMeta, Tag};
+
+/// Action offered by the context menu of an item.
+/// The game implements the actions by observing `SlotEvent<SlotContextAction>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContextAction {
+    Use,
+    Equip,
+    Split,
+    Drop,
+    Destroy,
+    Custom(String),
+}
+
+impl ContextAction {
+    pub fn label(&self) -> &str {
+        match self {
+            ContextAction::Use => "Use",
+            ContextAction::Equip => "Equip",
+            ContextAction::Split => "Split",
+            ContextAction::Drop => "Drop",
+            ContextAction::Destroy => "Destroy",
+            ContextAction::Custom(label) => label,
+        }
+    }
+}
+
+/// Actions offered by the context menu, registered by the game for item types and tags.
+#[derive(Resource, Default, Debug)]
+pub struct ContextMenus {
+    by_type_name: HashMap<String, Vec<ContextAction>>,
+    by_tag: Vec<(Tag, Vec<ContextAction>)>,
+}
+
+impl ContextMenus {
+    pub fn add_for_type(&mut self, type_name: &str, actions: impl IntoIterator<Item = ContextAction>) {
+        self.by_type_name.entry(type_name.to_string()).or_default().extend(actions);
+    }
+
+    /// Actions for items with the tag, or any of its child tags.
+    pub fn add_for_tag(&mut self, tag: Tag, actions: impl IntoIterator<Item = ContextAction>) {
+        self.by_tag.push((tag, actions.into_iter().collect()));
+    }
+
+    /// Actions of the item type first, then actions of its tags in the order they were added, without duplicates.
+    pub fn actions(&self, item: &ItemMeta) -> Vec<ContextAction> {
+        let by_tag = self.by_tag.iter()
+            .filter(|(tag, _)| item.tags.contains(tag))
+            .flat_map(|(_, actions)| actions);
+
+        let mut actions = Vec::<ContextAction>::new();
+        for action in self.by_type_name.get(item.type_name).into_iter().flatten().chain(by_tag) {
+            if !actions.contains(action) {
+                actions.push(action.clone());
+            }
+        }
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::{ItemType, ItemTypes, Items};
+    use super::*;
+
+    #[test]
+    fn test_context_menus() {
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "potion".into(),
+            display_name: "Potion".into(),
+            max_stack_size: 5,
+            tags: vec![Tag("consumable/drink".into())],
+            ..default()
+        });
+        let mut items = Items::default();
+        let potion = items.add_item("potion", &item_types).unwrap();
+
+        let mut menus = ContextMenus::default();
+        menus.add_for_tag(Tag("consumable".into()), [ContextAction::Use, ContextAction::Split, ContextAction::Drop]);
+        menus.add_for_tag(Tag("weapon".into()), [ContextAction::Equip]);
+        menus.add_for_type("potion", [ContextAction::Custom("Throw".into()), ContextAction::Drop]);
+
+        let meta = items.get_item_meta(potion, &item_types).unwrap();
+        assert_eq!(menus.actions(&meta), vec![
+            ContextAction::Custom("Throw".into()),
+            ContextAction::Drop,
+            ContextAction::Use,
+            ContextAction::Split,
+        ]);
+        assert_eq!(menus.actions(&meta)[0].label(), "Throw");
+    }
+}
@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+
+use crate::item::ItemId;
+
+/// Errors returned by `Items` and `Inventory` operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SiminvError {
+    /// There is no item with given id.
+    UnknownItemId(ItemId),
+    /// There is no item type registered with given type_name.
+    UnknownTypeName(String),
+    /// There is no space left in the collection.
+    CollectionFull(String),
+    /// The item would leave the collection bounds.
+    IndexOutOfBounds { collection: String, index: UVec2 },
+    /// The item would overlap other items.
+    IndexOccupied { collection: String, index: UVec2 },
+    /// There is no item at given index.
+    EmptyIndex { collection: String, index: UVec2 },
+    /// The item does not have tags required by the slot.
+    TagMismatch(ItemId),
+    /// The item does not pass the filter of the collection.
+    RejectedByCollection { collection: String, item: ItemId },
+    /// The stack has fewer items than requested.
+    NotEnoughItems { item: ItemId, available: u64 },
+    /// The stack can't be split into stacks of at least one item.
+    InvalidSplitAmount { item: ItemId, amount: u64 },
+    /// The stack would exceed max stack size of the item type.
+    StackOverflow { item: ItemId, max_stack_size: u64 },
+    /// None of the target slots can accept the item.
+    NoMatchingSlot(ItemId),
+    /// The collection would exceed its max weight.
+    TooHeavy { collection: String, item: ItemId },
+    /// A `MoveValidator` rejected the move.
+    MoveVetoed { item: ItemId, reason: String },
+}
+
+impl core::fmt::Display for SiminvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SiminvError::UnknownItemId(id) => write!(f, "unknown item id: {id:?}"),
+            SiminvError::UnknownTypeName(type_name) => write!(f, "unknown item type: {type_name}"),
+            SiminvError::CollectionFull(collection) => write!(f, "collection {collection} is full"),
+            SiminvError::IndexOutOfBounds { collection, index } => write!(f, "index {index} is out of bounds of collection {collection}"),
+            SiminvError::IndexOccupied { collection, index } => write!(f, "index {index} of collection {collection} is occupied"),
+            SiminvError::EmptyIndex { collection, index } => write!(f, "index {index} of collection {collection} is empty"),
+            SiminvError::TagMismatch(id) => write!(f, "item {id:?} does not have the required tags"),
+            SiminvError::RejectedByCollection { collection, item } => write!(f, "collection {collection} does not accept item {item:?}"),
+            SiminvError::NotEnoughItems { item, available } => write!(f, "item {item:?} has only {available} items in the stack"),
+            SiminvError::InvalidSplitAmount { item, amount } => write!(f, "can't split {amount} items off item {item:?}"),
+            SiminvError::StackOverflow { item, max_stack_size } => write!(f, "item {item:?} exceeds max stack size {max_stack_size}"),
+            SiminvError::NoMatchingSlot(id) => write!(f, "no slot can accept item {id:?}"),
+            SiminvError::TooHeavy { collection, item } => write!(f, "item {item:?} is too heavy for collection {collection}"),
+            SiminvError::MoveVetoed { item, reason } => write!(f, "item {item:?} can't be moved: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SiminvError {}
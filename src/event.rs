@@ -1,4 +1,6 @@
-use bevy::{ecs::event::EntityComponentsTrigger, prelude::*};
+use bevy::{ecs::event::EntityComponentsTrigger, picking::pointer::{PointerButton, PointerId}, prelude::*};
+
+use crate::{context_menu::ContextAction, error::SiminvError, input::modified_click::Modifiers, item::ItemId};
 
 
 #[derive(Debug, Clone, EntityEvent)]
@@ -29,10 +31,14 @@ impl<E> core::ops::Deref for SlotEvent<E> {
 pub struct SlotAdd;
 
 #[derive(Debug)]
-pub struct SlotOver;
+pub struct SlotOver {
+    pub pointer: PointerId,
+}
 
 #[derive(Debug)]
-pub struct SlotOut;
+pub struct SlotOut {
+    pub pointer: PointerId,
+}
 
 #[derive(Debug)]
 pub struct SlotUpdate;
@@ -41,24 +47,79 @@ pub struct SlotUpdate;
 pub struct SlotBackgroundAdd;
 
 #[derive(Debug)]
-pub struct SlotBackgroundOver;
+pub struct SlotBackgroundOver {
+    pub pointer: PointerId,
+}
 
 #[derive(Debug)]
-pub struct SlotBackgroundOut;
+pub struct SlotBackgroundOut {
+    pub pointer: PointerId,
+}
 
 #[derive(Debug)]
 pub struct SlotDoubleClick;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SlotShiftClick;
 
+/// Triggered on a slot for every click, except a plain left click, which is handled
+/// by dragging and double-clicking. Plain right and middle clicks trigger it with empty `modifiers`.
+#[derive(Debug)]
+pub struct SlotModifiedClick {
+    pub modifiers: Modifiers,
+    pub button: PointerButton,
+    pub pointer: PointerId,
+}
+
+#[derive(Debug)]
+pub struct SlotRightClick {
+    pub pointer: PointerId,
+}
+
+/// Triggered on a slot when an action of the context menu of its item is chosen.
+#[derive(Debug)]
+pub struct SlotContextAction {
+    pub item: ItemId,
+    pub action: ContextAction,
+}
+
 #[derive(Debug)]
-pub struct SlotHover;
+pub struct SlotHover {
+    pub pointer: PointerId,
+}
 
 #[derive(Debug)]
-pub struct SlotHoverOver;
+pub struct SlotHoverOver {
+    pub pointer: PointerId,
+}
+
+/// Triggered on a slot to split `amount` items off the stack in the `from` slot into it.
+#[derive(Debug)]
+pub struct SlotSplit {
+    pub from: Entity,
+    pub amount: u64,
+}
+
+/// Triggered on a slot when a ctrl-drag is dropped into it and `SplitDrag::ask_amount` is enabled.
+/// Ask for the amount, e.g. in a dialog, then trigger `SlotSplit`.
+#[derive(Debug)]
+pub struct SlotSplitRequest {
+    pub from: Entity,
+    pub pointer: PointerId,
+    /// Suggested amount, half of the stack.
+    pub amount: u64,
+    /// At least one item has to stay in the stack.
+    pub max_amount: u64,
+}
+
+/// Triggered on a slot when an interaction with it fails, e.g. a dropped item does not fit.
+#[derive(Debug)]
+pub struct SlotError {
+    pub error: SiminvError,
+}
 
-pub(crate) trait TriggerSlotEvent {
+/// Triggers `SlotEvent`s, so they are observed by observers of the slot and its components' markers.
+pub trait TriggerSlotEvent {
     fn trigger_slot_event<E: Send + Sync + 'static>(&mut self, event: SlotEvent<E>);
 }
 
@@ -1,5 +1,5 @@
 use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
-use crate::{item::Tag, slot::{InventoryHandle, Slot}, slot_background::SlotBackground};
+use crate::{inventory::Inventory, item::TagRule, slot::{InventoryHandle, Slot}, slot_background::SlotBackground};
 
 /// Defines slot sizes and gaps beetween slots.
 pub struct GridStyle {
@@ -25,7 +25,7 @@ pub struct GridInventoryConfig<'a> {
     pub collection: &'a str,
     pub columns: usize,
     pub rows: usize,
-    pub required_tags: HashMap<UVec2, Tag>,
+    pub required_tags: HashMap<UVec2, TagRule>,
     /// indexes that are not to be displayed as slots
     pub blocked_indexes: HashSet<UVec2>,
 }
@@ -42,6 +42,25 @@ impl<'a> Default for GridInventoryConfig<'a> {
     }
 }
 
+/// Blocked indexes of a grid, registered in the `Inventory` when the grid is spawned,
+/// so no item can be moved there, even without the ui. They replace the blocked indexes
+/// of the collection and are not saved with the inventory.
+#[derive(Component, Debug, Clone)]
+pub struct GridLayout {
+    pub collection: String,
+    pub blocked_indexes: HashSet<UVec2>,
+}
+
+pub(crate) fn on_grid_layout_add(
+    added: On<Add, GridLayout>,
+    query: Query<&GridLayout>,
+    mut inventory: ResMut<Inventory>,
+) {
+    let Ok(layout) = query.get(added.entity) else { return };
+    // indexes unblocked by a new layout are usable again
+    inventory.set_blocked_indexes(&layout.collection, layout.blocked_indexes.clone());
+}
+
 /// Helper function to build grid Bundle.
 pub fn build_grid_inventory<T: Bundle + Default>(
     style: &GridStyle,
@@ -61,6 +80,10 @@ pub fn build_grid_inventory<T: Bundle + Default>(
             height: Val::Auto,
             ..default()
         },
+        GridLayout {
+            collection: config.collection.to_string(),
+            blocked_indexes: config.blocked_indexes.clone(),
+        },
         Children::spawn(SpawnIter(
             (0..config.columns)
                 .flat_map(move |x| (0..config.rows).map(move |y| (x, y)))
@@ -74,7 +97,7 @@ pub fn build_grid_inventory<T: Bundle + Default>(
                 .map(|index| {
                     let size = Val2::new(style.slot_width, style.slot_height);
                     let mut slot = Slot::empty();
-                    slot.required_tag = config.required_tags.get(&index).cloned();
+                    slot.required_tags = config.required_tags.get(&index).cloned();
                     build_slot_with_background::<T>(size, slot, index, InventoryHandle {
                         collection: config.collection.to_string(),
                         index,
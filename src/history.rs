@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{inventory::Inventory, item::{Item, ItemId, ItemTypes, Items}};
+
+/// Changes made by a single user action, e.g. a drag and drop that merged some stacks.
+#[derive(Debug, Clone, Default)]
+struct HistoryEntry {
+    // collection, index, item before and after the action
+    indexes: Vec<(String, UVec2, Option<ItemId>, Option<ItemId>)>,
+    // item state before and after the action
+    items: Vec<(ItemId, Option<Item>, Option<Item>)>,
+}
+
+impl HistoryEntry {
+    fn is_empty(&self) -> bool {
+        self.indexes.is_empty() && self.items.is_empty()
+    }
+}
+
+/// Bounded undo/redo history of `Inventory` and `Items` changes.
+///
+/// With the `SiminvPlugin`, changes made during one frame are recorded as one entry.
+/// Without it, call `record` after every action.
+#[derive(Resource, Debug)]
+pub struct History {
+    undo: VecDeque<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::with_limit(100)
+    }
+}
+
+impl History {
+    /// Keeps at most `limit` entries, the oldest are dropped first.
+    pub fn with_limit(limit: usize) -> Self {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Records all changes made since the last call as one entry.
+    /// A new entry discards the entries that could be redone.
+    pub fn record(&mut self, inventory: &mut Inventory, items: &mut Items) {
+        let mut entry = HistoryEntry::default();
+        for ((collection, index), old) in inventory.take_journal() {
+            let new = inventory.get(&collection, &index).copied();
+            if old != new {
+                entry.indexes.push((collection, index, old, new));
+            }
+        }
+        for (id, old) in items.take_journal() {
+            let new = items.get_item(id).cloned();
+            if old != new {
+                entry.items.push((id, old, new));
+            }
+        }
+
+        if entry.is_empty() || self.limit == 0 {
+            return
+        }
+        self.redo.clear();
+        self.undo.push_back(entry);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Reverts the last recorded entry. Returns false if there is nothing to undo.
+    pub fn undo(&mut self, inventory: &mut Inventory, items: &mut Items, item_types: &ItemTypes) -> bool {
+        // changes that were not recorded yet would be lost
+        self.record(inventory, items);
+        let Some(entry) = self.undo.pop_back() else { return false };
+        apply(&entry, inventory, items, item_types, true);
+        self.redo.push(entry);
+        true
+    }
+
+    /// Applies the last undone entry again. Returns false if there is nothing to redo.
+    pub fn redo(&mut self, inventory: &mut Inventory, items: &mut Items, item_types: &ItemTypes) -> bool {
+        // new changes discard the entries that could be redone
+        self.record(inventory, items);
+        let Some(entry) = self.redo.pop() else { return false };
+        apply(&entry, inventory, items, item_types, false);
+        self.undo.push_back(entry);
+        true
+    }
+}
+
+// restores the state before the entry when undoing, or after the entry when redoing
+fn apply(entry: &HistoryEntry, inventory: &mut Inventory, items: &mut Items, item_types: &ItemTypes, undo: bool) {
+    // items are restored before the inventory, so it knows their shapes
+    for (id, old, new) in &entry.items {
+        items.restore(*id, if undo { old.clone() } else { new.clone() });
+    }
+
+    let states = entry.indexes.iter()
+        .map(|(collection, index, old, new)| (collection.clone(), *index, if undo { *old } else { *new }))
+        .collect::<Vec<_>>();
+    inventory.restore(&states, items, item_types);
+
+    // stack sizes or properties of items may have changed without moving them
+    let changed = entry.items.iter().map(|(id, _, _)| *id).collect::<Vec<_>>();
+    inventory.mark_modified(&changed);
+}
+
+pub(crate) fn record_history(
+    mut history: ResMut<History>,
+    mut inventory: ResMut<Inventory>,
+    mut items: ResMut<Items>,
+) {
+    // journals are not persisted, so draining them should not trigger saving the resources
+    history.record(inventory.bypass_change_detection(), items.bypass_change_detection());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{item::ItemType, move_validator::MoveValidators};
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "stones".into(),
+            display_name: "Stones".into(),
+            max_stack_size: 20,
+            ..default()
+        });
+        let mut items = Items::default();
+        let mut inventory = Inventory::default();
+        let mut history = History::with_limit(2);
+
+        let stones = items.add_items("stones", 15, &item_types).unwrap()[0];
+        let more_stones = items.add_items("stones", 10, &item_types).unwrap()[0];
+        inventory.add("main", stones, &items, &item_types, &validators).unwrap();
+        inventory.add("main", more_stones, &items, &item_types, &validators).unwrap();
+        history.record(&mut inventory, &mut items);
+
+        // merge fills one stack and leaves the rest in the other one, as one action
+        inventory.transfer("main", UVec2::new(1, 0), "main", UVec2::new(0, 0), &mut items, &item_types, &validators).unwrap();
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 20);
+        assert_eq!(items.get_item(more_stones).unwrap().stack_size, 5);
+
+        assert!(history.undo(&mut inventory, &mut items, &item_types));
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 15);
+        assert_eq!(items.get_item(more_stones).unwrap().stack_size, 10);
+        assert!(inventory.take_modified().contains(&("main".into(), UVec2::new(0, 0))));
+
+        assert!(history.redo(&mut inventory, &mut items, &item_types));
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 20);
+        assert!(!history.can_redo());
+
+        // undo everything, including adding the items
+        assert!(history.undo(&mut inventory, &mut items, &item_types));
+        assert!(history.undo(&mut inventory, &mut items, &item_types));
+        assert!(!history.undo(&mut inventory, &mut items, &item_types));
+        assert!(inventory.is_empty());
+        assert!(items.get_item(stones).is_none());
+
+        // a new action discards the undone entries
+        assert!(history.redo(&mut inventory, &mut items, &item_types));
+        inventory.consume("main", UVec2::new(0, 0), 1, &mut items).unwrap();
+        history.record(&mut inventory, &mut items);
+        assert!(!history.can_redo());
+    }
+}
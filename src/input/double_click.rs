@@ -1,6 +1,6 @@
 use core::time::Duration;
 
-use bevy::prelude::*;
+use bevy::{picking::pointer::PointerButton, prelude::*};
 
 use crate::event::{SlotDoubleClick, SlotEvent, TriggerSlotEvent};
 
@@ -26,7 +26,7 @@ pub(crate) fn on_click<F: Component>(
     query: Query<&F>,
 ) {
     // not observed component
-    if !query.contains(click.entity) {
+    if !query.contains(click.entity) || click.button != PointerButton::Primary {
         return
     }
 
@@ -1,6 +1,6 @@
 use core::time::Duration;
 
-use bevy::prelude::*;
+use bevy::{picking::pointer::PointerId, platform::collections::HashMap, prelude::*};
 
 use crate::event::{SlotEvent, SlotHover, SlotHoverOver, TriggerSlotEvent};
 
@@ -17,14 +17,15 @@ enum HoverState {
 #[derive(Resource)]
 pub(crate) struct Hover {
     duration: Duration,
-    state: HoverState,
+    // every pointer hovers independently
+    states: HashMap<PointerId, HoverState>,
 }
 
 impl Default for Hover {
     fn default() -> Self {
         Self {
             duration: Duration::from_secs_f64(0.5),
-            state: HoverState::None,
+            states: HashMap::new(),
         }
     }
 }
@@ -38,15 +39,15 @@ pub(crate) fn on_over<F: Component>(
         return
     }
 
-    if hover.state != HoverState::None {
-        // TODO: swap it for logging library
-        println!("overlapping hover, it may not work properly");
+    if hover.states.get(&over.pointer_id).is_some_and(|state| *state != HoverState::None) {
+        warn!("overlapping hover, it may not work properly");
     }
 
-    hover.state = HoverState::Preparing { 
+    let state = HoverState::Preparing { 
         entity: over.entity,
         timer: Timer::new(hover.duration, TimerMode::Once),
     };
+    hover.states.insert(over.pointer_id, state);
 }
 
 pub(crate) fn on_out<F: Component>(
@@ -59,11 +60,20 @@ pub(crate) fn on_out<F: Component>(
         return
     }
 
-    if hover.state == HoverState::Triggered {
-        commands.trigger_slot_event(SlotEvent::new(out.entity, SlotHoverOver));
+    if hover.states.remove(&out.pointer_id) == Some(HoverState::Triggered) {
+        commands.trigger_slot_event(SlotEvent::new(out.entity, SlotHoverOver { pointer: out.pointer_id }));
     }
+}
 
-    hover.state = HoverState::None;
+// pointers that went away, e.g. lifted touches, do not hover anymore
+pub(crate) fn on_pointer_remove(
+    remove: On<Remove, PointerId>,
+    query: Query<&PointerId>,
+    mut hover: ResMut<Hover>,
+) {
+    if let Ok(pointer) = query.get(remove.entity) {
+        hover.states.remove(pointer);
+    }
 }
 
 pub(crate) fn update_time(
@@ -71,16 +81,15 @@ pub(crate) fn update_time(
     mut commands: Commands,
     mut hover: ResMut<Hover>,
 ) {
-    match &mut hover.state { 
-        HoverState::Preparing { entity, timer } => {
+    for (pointer, state) in hover.states.iter_mut() {
+        if let HoverState::Preparing { entity, timer } = state {
             if timer.is_finished() {
-                commands.trigger_slot_event(SlotEvent::new(*entity, SlotHover));
-                hover.state = HoverState::Triggered;
+                commands.trigger_slot_event(SlotEvent::new(*entity, SlotHover { pointer: *pointer }));
+                *state = HoverState::Triggered;
             } else {
                 timer.tick(time.delta());
             }
-        },
-        _ => {},
+        }
     }
 }
 
@@ -1,3 +1,6 @@
 pub mod double_click;
 pub mod hover;
+pub mod modified_click;
+pub mod right_click;
 pub mod shift_click;
+pub mod split_drag;
@@ -0,0 +1,178 @@
+use core::ops::BitOr;
+
+use bevy::{picking::pointer::PointerButton, prelude::*};
+
+use crate::{event::{SlotEvent, SlotModifiedClick, SlotShiftClick, TriggerSlotEvent}, input::shift_click::SHIFT_CLICK};
+
+/// Modifier keys held during a click, combined with `|`, e.g. `Modifiers::CTRL | Modifiers::ALT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// Cmd on macOS, Windows key elsewhere.
+    pub super_key: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers { ctrl: false, alt: false, shift: false, super_key: false };
+    pub const CTRL: Modifiers = Modifiers { ctrl: true, ..Modifiers::NONE };
+    pub const ALT: Modifiers = Modifiers { alt: true, ..Modifiers::NONE };
+    pub const SHIFT: Modifiers = Modifiers { shift: true, ..Modifiers::NONE };
+    pub const SUPER: Modifiers = Modifiers { super_key: true, ..Modifiers::NONE };
+
+    /// Left and right keys are not distinguished.
+    pub fn pressed(keys: &ButtonInput<KeyCode>) -> Self {
+        Modifiers {
+            ctrl: keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]),
+            alt: keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]),
+            shift: keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+            super_key: keys.any_pressed([KeyCode::SuperLeft, KeyCode::SuperRight]),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Modifiers::NONE
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Modifiers {
+            ctrl: self.ctrl || rhs.ctrl,
+            alt: self.alt || rhs.alt,
+            shift: self.shift || rhs.shift,
+            super_key: self.super_key || rhs.super_key,
+        }
+    }
+}
+
+/// Modifier keys that have to be held, exactly, while clicking with the button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClickBinding {
+    pub modifiers: Modifiers,
+    pub button: PointerButton,
+}
+
+impl ClickBinding {
+    pub fn new(modifiers: Modifiers, button: PointerButton) -> Self {
+        ClickBinding {
+            modifiers,
+            button,
+        }
+    }
+}
+
+type BoundTrigger = Box<dyn Fn(&mut Commands, Entity) + Send + Sync>;
+
+/// Clicks bound to slot events, so they can be observed e.g. by `on_event_move_to`.
+/// By default, shift-click triggers `SlotShiftClick`.
+///
+/// Every click, except a plain left click, also triggers `SlotModifiedClick`.
+#[derive(Resource)]
+pub struct ClickBindings {
+    bindings: Vec<(ClickBinding, BoundTrigger)>,
+}
+
+impl Default for ClickBindings {
+    fn default() -> Self {
+        let mut bindings = ClickBindings::empty();
+        bindings.bind(SHIFT_CLICK, SlotShiftClick);
+        bindings
+    }
+}
+
+impl ClickBindings {
+    pub fn empty() -> Self {
+        ClickBindings {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Triggers `SlotEvent<E>` on the clicked slot. A click may trigger several events.
+    pub fn bind<E: Clone + Send + Sync + 'static>(&mut self, binding: ClickBinding, event: E) -> &mut Self {
+        self.bindings.push((binding, Box::new(move |commands, entity| {
+            commands.trigger_slot_event(SlotEvent::new(entity, event.clone()));
+        })));
+        self
+    }
+
+    /// Removes all events bound to the click.
+    pub fn unbind(&mut self, binding: ClickBinding) -> &mut Self {
+        self.bindings.retain(|(bound, _)| *bound != binding);
+        self
+    }
+
+    pub fn is_bound(&self, binding: ClickBinding) -> bool {
+        self.bound(binding).next().is_some()
+    }
+
+    // modifiers have to match exactly, ctrl-shift-click does not trigger ctrl-click
+    fn bound(&self, binding: ClickBinding) -> impl Iterator<Item = &BoundTrigger> {
+        self.bindings.iter()
+            .filter(move |(bound, _)| *bound == binding)
+            .map(|(_, trigger)| trigger)
+    }
+}
+
+pub(crate) fn on_click<F: Component>(
+    click: On<Pointer<Click>>,
+    mut commands: Commands,
+    // read when the click happens, picking runs before the systems of the frame
+    keys: Res<ButtonInput<KeyCode>>,
+    bindings: Res<ClickBindings>,
+    query: Query<&F>,
+) {
+    // not observed component
+    if !query.contains(click.entity) {
+        return
+    }
+
+    let binding = ClickBinding::new(Modifiers::pressed(&keys), click.button);
+    // plain left clicks are dragged and double-clicked, but they can still be bound
+    if !binding.modifiers.is_empty() || binding.button != PointerButton::Primary {
+        commands.trigger_slot_event(SlotEvent::new(click.entity, SlotModifiedClick {
+            modifiers: binding.modifiers,
+            button: binding.button,
+            pointer: click.pointer_id,
+        }));
+    }
+
+    for trigger in bindings.bound(binding) {
+        trigger(&mut commands, click.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_click_bindings() {
+        let mut keys = ButtonInput::<KeyCode>::default();
+        keys.press(KeyCode::ShiftRight);
+        keys.press(KeyCode::ControlLeft);
+        assert_eq!(Modifiers::pressed(&keys), Modifiers::CTRL | Modifiers::SHIFT);
+
+        let mut bindings = ClickBindings::default();
+        assert!(bindings.is_bound(SHIFT_CLICK));
+
+        let ctrl_alt_click = ClickBinding::new(Modifiers::CTRL | Modifiers::ALT, PointerButton::Primary);
+        bindings.bind(ctrl_alt_click, SlotShiftClick);
+        bindings.bind(ctrl_alt_click, SlotShiftClick);
+        assert_eq!(bindings.bound(ctrl_alt_click).count(), 2);
+
+        // modifiers and buttons have to match exactly
+        assert!(!bindings.is_bound(ClickBinding::new(Modifiers::CTRL, PointerButton::Primary)));
+        assert!(!bindings.is_bound(ClickBinding::new(Modifiers::CTRL | Modifiers::ALT | Modifiers::SHIFT, PointerButton::Primary)));
+        assert!(!bindings.is_bound(ClickBinding::new(Modifiers::CTRL | Modifiers::ALT, PointerButton::Secondary)));
+        assert!(!bindings.is_bound(ClickBinding::new(Modifiers::NONE, PointerButton::Middle)));
+
+        bindings.unbind(ctrl_alt_click);
+        assert!(!bindings.is_bound(ctrl_alt_click));
+        assert!(bindings.is_bound(SHIFT_CLICK));
+        assert!(!ClickBindings::empty().is_bound(SHIFT_CLICK));
+    }
+}
@@ -0,0 +1,22 @@
+use bevy::{picking::pointer::PointerButton, prelude::*};
+
+use crate::{event::{SlotEvent, SlotRightClick, TriggerSlotEvent}, input::modified_click::Modifiers};
+
+pub(crate) fn on_click<F: Component>(
+    click: On<Pointer<Click>>,
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    query: Query<&F>,
+) {
+    // right clicks with modifiers are left for click bindings
+    if click.button != PointerButton::Secondary || !Modifiers::pressed(&keys).is_empty() {
+        return
+    }
+
+    // not observed component
+    if !query.contains(click.entity) {
+        return
+    }
+
+    commands.trigger_slot_event(SlotEvent::new(click.entity, SlotRightClick { pointer: click.pointer_id }));
+}
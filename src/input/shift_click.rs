@@ -1,34 +1,11 @@
-use bevy::prelude::*;
+use bevy::picking::pointer::PointerButton;
 
-use crate::event::{SlotEvent, SlotShiftClick, TriggerSlotEvent};
+use crate::input::modified_click::{ClickBinding, Modifiers};
 
-#[derive(Resource, Default)]
-pub(crate) struct ShiftClick {
-    shift_pressed: bool,
-}
-
-pub(crate) fn detect_shift_press(
-    keys: Res<ButtonInput<KeyCode>>,
-    mut shift_click: ResMut<ShiftClick>,
-) {
-    shift_click.shift_pressed = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
-}
-
-pub(crate) fn on_click<F: Component>(
-    click: On<Pointer<Click>>,
-    mut commands: Commands,
-    shift_click: Res<ShiftClick>,
-    query: Query<&F>
-) {
-    if !shift_click.shift_pressed {
-        return
-    }
-
-    // not observed component
-    if !query.contains(click.entity) {
-        return
-    }
-
-    commands.trigger_slot_event(SlotEvent::new(click.entity, SlotShiftClick));
-}
+pub use crate::event::SlotShiftClick;
 
+/// Shift-click, bound to `SlotShiftClick` by default. Unbind it from `ClickBindings` to disable it.
+pub const SHIFT_CLICK: ClickBinding = ClickBinding {
+    modifiers: Modifiers::SHIFT,
+    button: PointerButton::Primary,
+};
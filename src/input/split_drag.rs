@@ -0,0 +1,28 @@
+use bevy::{picking::pointer::PointerButton, prelude::*};
+
+use crate::{input::modified_click::Modifiers, pointer::DragSplit};
+
+/// Drags that split the stack: ctrl-drag takes half of the stack, right-drag takes one item.
+#[derive(Resource, Default)]
+pub struct SplitDrag {
+    /// Ctrl-drags trigger `SlotSplitRequest` when dropped, so a dialog can ask for the amount,
+    /// instead of splitting half of the stack right away.
+    pub ask_amount: bool,
+}
+
+impl SplitDrag {
+    pub fn with_ask_amount(ask_amount: bool) -> Self {
+        SplitDrag {
+            ask_amount,
+        }
+    }
+
+    // part of the stack taken by a drag started with the button
+    pub(crate) fn split(&self, button: PointerButton, modifiers: Modifiers) -> Option<DragSplit> {
+        match button {
+            PointerButton::Secondary => Some(DragSplit::One),
+            PointerButton::Primary if modifiers.ctrl => Some(DragSplit::Half),
+            _ => None,
+        }
+    }
+}
@@ -1,16 +1,97 @@
-use bevy::{platform::collections::HashMap, prelude::*};
+use std::cmp::Ordering;
+
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
 use serde::{Deserialize, Serialize};
 
-use crate::{event::{SlotEvent, SlotUpdate}, item::ItemId, slot::{InventoryHandle, Slot}};
+use crate::{auto_move::{MoveCandidate, MovePolicy}, error::SiminvError, move_validator::{ItemMove, MoveValidators}, event::{SlotEvent, SlotUpdate}, item::{Item, ItemHolder, ItemId, ItemMeta, ItemShape, ItemTypes, Items, Tag, TagRule}, slot::{InventoryHandle, Slot}};
 
 // TODO: consider a different data structure that HashMap<String, _> and HashMap<UVec2, _> 
 // for storing and fast access to data
-#[derive(Resource, Default, Deserialize, Serialize)]
+#[derive(Resource, Default, Clone, Deserialize, Serialize)]
 pub struct Inventory {
     collections_by_name: HashMap<String, InventoryCollection>,
     // a list of indexes that should be updated by the ui
     #[serde(skip)]
     modified: Vec<(String, UVec2)>,
+    // changes waiting to be sent as `InventoryChanged` messages
+    #[serde(skip)]
+    changes: Vec<InventoryChanged>,
+    // items at the changed indexes before the first change, used by `History`
+    #[serde(skip)]
+    journal: HashMap<(String, UVec2), Option<ItemId>>,
+    // changes of the collections made by a running transaction, undone if it fails
+    #[serde(skip)]
+    undo: Option<Vec<Undo>>,
+}
+
+/// Why the item at an index has changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeReason {
+    /// The item was dragged and dropped by a pointer.
+    Drag,
+    /// The item was moved by `Inventory::quick_move`, e.g. on double-click.
+    AutoMove,
+    /// The item was moved by `Inventory::transfer`.
+    Transfer,
+    /// The item was placed by `Inventory::add` or `Inventory::set`.
+    Add,
+    /// Another stack was merged into the item.
+    Merge,
+    /// A part of the stack was split off.
+    Split,
+    /// The item was removed, or a part of the stack was consumed.
+    Remove,
+    /// The item was written back from a `Slot` displaying the index.
+    Slot,
+    /// The item was restored by `History::undo` or `History::redo`.
+    History,
+    /// The collection was rearranged by `Inventory::sort_collection`.
+    Sort,
+    /// The stack was merged into other stacks by `Inventory::stack_all` or `Inventory::quick_stack`.
+    Stack,
+}
+
+/// Order of the items arranged by `Inventory::sort_collection`.
+/// Items that are equal by the key are ordered by type name, then by stack size.
+#[derive(Debug, Clone, Copy)]
+pub enum SortKey {
+    TypeName,
+    DisplayName,
+    /// By the first tag of the item type, items without tags go last.
+    Tag,
+    /// Largest stacks first.
+    StackSize,
+    Custom(fn(&ItemMeta, &ItemMeta) -> Ordering),
+}
+
+impl SortKey {
+    fn compare(&self, a: &ItemMeta, b: &ItemMeta) -> Ordering {
+        let ordering = match self {
+            SortKey::TypeName => a.type_name.cmp(b.type_name),
+            SortKey::DisplayName => a.display_name.cmp(b.display_name),
+            SortKey::Tag => match (a.tags.first(), b.tags.first()) {
+                (Some(Tag(a)), Some(Tag(b))) => a.cmp(b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            },
+            SortKey::StackSize => b.stack_size.cmp(&a.stack_size),
+            SortKey::Custom(compare) => compare(a, b),
+        };
+        ordering
+            .then_with(|| a.type_name.cmp(b.type_name))
+            .then_with(|| b.stack_size.cmp(&a.stack_size))
+    }
+}
+
+/// Sent when the item at an index of the inventory changes, so gameplay systems
+/// (quests, achievements, stats) can react without polling the `Inventory`.
+/// `old` and `new` are the same when only the stack size has changed.
+#[derive(Message, Debug, Clone, PartialEq, Eq)]
+pub struct InventoryChanged {
+    pub collection: String,
+    pub index: UVec2,
+    pub old: Option<ItemId>,
+    pub new: Option<ItemId>,
+    pub reason: ChangeReason,
 }
 
 impl Inventory {
@@ -20,26 +101,77 @@ impl Inventory {
             .and_then(|collection| collection.get(index))
     }
 
-    pub fn set(&mut self, collection: &str, index: UVec2, item: ItemId) {
-        self.set_unregistered(collection, index, item);
-        self.modified.push((collection.to_string(), index));
+    /// Returns the anchor index and the item occupying given cell of the collection.
+    pub fn get_occupant(&self, collection: &str, index: &UVec2) -> Option<(UVec2, ItemId)> {
+        self.collections_by_name
+            .get(collection)
+            .and_then(|collection| collection.get_occupant(index))
+    }
+
+    /// Returns true if the item can be placed at `index`.
+    /// Items anchored at `ignored` indexes are treated as if they were not there.
+    pub fn fits(&self, collection: &str, index: UVec2, item: ItemId, items: &Items, item_types: &ItemTypes, ignored: &[UVec2]) -> bool {
+        self.check_fits(collection, index, item, items, item_types, ignored).is_ok()
+    }
+
+    /// Same as `fits`, but returns the reason why the item does not fit.
+    pub fn check_fits(&self, collection: &str, index: UVec2, item: ItemId, items: &Items, item_types: &ItemTypes, ignored: &[UVec2]) -> Result<(), SiminvError> {
+        let (shape, tags) = item_shape_and_tags(item, items, item_types)?;
+        self.collection_or_default(collection)
+            .fits(index, shape, tags, ignored)
+            .map_err(|misfit| misfit.into_error(collection, index, item))
+    }
+
+    fn collection_or_default(&self, collection: &str) -> &InventoryCollection {
+        static EMPTY: std::sync::LazyLock<InventoryCollection> = std::sync::LazyLock::new(InventoryCollection::default);
+        self.collections_by_name.get(collection).unwrap_or(&EMPTY)
+    }
+
+    /// Places the item at `index`, replacing the item anchored there.
+    /// Fails if the item would overlap other items, leave the collection bounds
+    /// or lacks the tags required at `index`, or if the stack exceeds its max stack size.
+    pub fn set(&mut self, collection: &str, index: UVec2, item: ItemId, items: &Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        items.check_stack_size(item, item_types)?;
+        self.check_fits(collection, index, item, items, item_types, &[index])?;
+        let old = self.get(collection, &index).copied();
+        let replaced_weight = old.map_or(0., |old| items.weight(old, item_types));
+        self.check_weight(collection, item, items.weight(item, item_types) - replaced_weight, items, item_types)?;
+        validators.check(&ItemMove { item, from: None, to: Some(handle(collection, index)), replaced: old }, self, items, item_types)?;
+
+        let (shape, _) = item_shape_and_tags(item, items, item_types)?;
+        self.set_unregistered(collection, index, item, shape);
+        self.record_change(collection, index, old, ChangeReason::Add);
+        Ok(())
     }
 
-    fn set_unregistered(&mut self, collection: &str, index: UVec2, item: ItemId) {
+    fn set_unregistered(&mut self, collection: &str, index: UVec2, item: ItemId, shape: &ItemShape) {
+        if let Some(undo) = &mut self.undo {
+            match self.collections_by_name.get(collection) {
+                Some(existing) => undo.push(Undo::Anchor(collection.to_string(), existing.snapshot(index, Some(shape)))),
+                None => undo.push(Undo::Collection(collection.to_string())),
+            }
+        }
         self.collections_by_name
             .entry(collection.to_string())
             .or_default()
-            .set(index, item);
+            .set(index, item, shape);
     }
 
-    pub fn remove(&mut self, collection: &str, index: UVec2) {
-        self.remove_unregistered(collection, index);
-        self.modified.push((collection.to_string(), index));
+    /// Removes the item anchored at `index` and returns it.
+    pub fn remove(&mut self, collection: &str, index: UVec2) -> Result<ItemId, SiminvError> {
+        let removed = self.remove_unregistered(collection, index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: collection.to_string(), index })?;
+        self.record_change(collection, index, Some(removed), ChangeReason::Remove);
+        Ok(removed)
     }
 
-    fn remove_unregistered(&mut self, collection: &str, index: UVec2) {
-        if let Some(collection) = self.collections_by_name
-            .get_mut(&collection.to_string()) { collection.remove(&index) }
+    fn remove_unregistered(&mut self, collection: &str, index: UVec2) -> Option<ItemId> {
+        if let (Some(undo), Some(existing)) = (&mut self.undo, self.collections_by_name.get(collection)) {
+            undo.push(Undo::Anchor(collection.to_string(), existing.snapshot(index, None)));
+        }
+        self.collections_by_name
+            .get_mut(collection)
+            .and_then(|collection| collection.remove(&index))
     }
 
     pub fn set_max_size(&mut self, collection: &str, max_size: UVec2) {
@@ -49,17 +181,601 @@ impl Inventory {
             .max_size = max_size;
     }
 
-    pub fn add(&mut self, collection: &str, item: ItemId) -> Option<UVec2> {
-        let maybe_added = self.collections_by_name
+    /// Items placed over `index` must match the `rule`.
+    pub fn set_required_tags(&mut self, collection: &str, index: UVec2, rule: Option<TagRule>) {
+        let required_tags = &mut self.collections_by_name
+            .entry(collection.to_string())
+            .or_default()
+            .required_tags;
+        match rule {
+            Some(rule) => required_tags.insert(index, rule),
+            None => required_tags.remove(&index),
+        };
+    }
+
+    pub fn required_tags(&self, collection: &str, index: &UVec2) -> Option<&TagRule> {
+        self.collections_by_name
+            .get(collection)
+            .and_then(|collection| collection.required_tags.get(index))
+    }
+
+    /// Blocked indexes can't be covered by any item, e.g. gaps in an equipment layout.
+    pub fn set_blocked(&mut self, collection: &str, index: UVec2, blocked: bool) {
+        let collection = self.collections_by_name
+            .entry(collection.to_string())
+            .or_default();
+        if blocked {
+            collection.blocked.insert(index);
+        } else {
+            collection.blocked.remove(&index);
+        }
+    }
+
+    /// Replaces all blocked indexes of the collection.
+    pub fn set_blocked_indexes(&mut self, collection: &str, indexes: HashSet<UVec2>) {
+        self.collections_by_name
+            .entry(collection.to_string())
+            .or_default()
+            .blocked = indexes;
+    }
+
+    pub fn is_blocked(&self, collection: &str, index: &UVec2) -> bool {
+        self.collections_by_name
+            .get(collection)
+            .is_some_and(|collection| collection.blocked.contains(index))
+    }
+
+    /// Only items matching the filter can be placed into the collection,
+    /// e.g. `TagRule::AnyOf(vec![Tag("ammo".into())])` for an ammo pouch, or `TagRule::NoneOf(..)` to reject some items.
+    /// Items already in the collection are kept.
+    pub fn set_filter(&mut self, collection: &str, filter: Option<TagRule>) {
+        self.collections_by_name
             .entry(collection.to_string())
             .or_default()
-            .add(item);
+            .filter = filter;
+    }
+
+    pub fn filter(&self, collection: &str) -> Option<&TagRule> {
+        self.collections_by_name
+            .get(collection)
+            .and_then(|collection| collection.filter.as_ref())
+    }
+
+    /// Whether items with given tags pass the filter of the collection.
+    pub fn accepts(&self, collection: &str, tags: &[Tag]) -> bool {
+        self.collection_or_default(collection).accepts(tags)
+    }
+
+    /// Items can be added to the collection only while its total weight stays within `max_weight`.
+    /// Items already in the collection are kept, even if they are heavier.
+    pub fn set_max_weight(&mut self, collection: &str, max_weight: Option<f32>) {
+        self.collections_by_name
+            .entry(collection.to_string())
+            .or_default()
+            .max_weight = max_weight;
+    }
+
+    pub fn max_weight(&self, collection: &str) -> Option<f32> {
+        self.collections_by_name
+            .get(collection)
+            .and_then(|collection| collection.max_weight)
+    }
+
+    /// Total weight of the items in the collection, including whole stacks.
+    pub fn weight(&self, collection: &str, items: &Items, item_types: &ItemTypes) -> f32 {
+        self.collection_or_default(collection)
+            .by_index
+            .values()
+            .map(|item| items.weight(*item, item_types))
+            .sum()
+    }
+
+    // fails if `added` weight would push the collection over its max weight
+    // taking weight away is always allowed, even from collections that are already too heavy
+    pub(crate) fn check_weight(&self, collection: &str, item: ItemId, added: f32, items: &Items, item_types: &ItemTypes) -> Result<(), SiminvError> {
+        // tolerates rounding errors of the summed weights
+        const TOLERANCE: f32 = 1e-4;
+        let Some(max_weight) = self.max_weight(collection) else { return Ok(()) };
+        if added > 0. && self.weight(collection, items, item_types) + added > max_weight + TOLERANCE {
+            return Err(SiminvError::TooHeavy { collection: collection.to_string(), item })
+        }
+        Ok(())
+    }
+
+    /// Inserts the item into the first index where it fits.
+    pub fn add(&mut self, collection: &str, item: ItemId, items: &Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<UVec2, SiminvError> {
+        items.check_stack_size(item, item_types)?;
+        let (shape, tags) = item_shape_and_tags(item, items, item_types)?;
+        if !self.accepts(collection, tags) {
+            return Err(SiminvError::RejectedByCollection { collection: collection.to_string(), item })
+        }
+        self.check_weight(collection, item, items.weight(item, item_types), items, item_types)?;
+        let added = self.collection_or_default(collection)
+            .find_free(shape, tags)
+            .ok_or_else(|| SiminvError::CollectionFull(collection.to_string()))?;
+        validators.check(&ItemMove { item, from: None, to: Some(handle(collection, added)), replaced: None }, self, items, item_types)?;
+
+        self.set_unregistered(collection, added, item, shape);
+        self.record_change(collection, added, None, ChangeReason::Add);
+        Ok(added)
+    }
+
+    /// Returns the reason why `transfer` with the same arguments would fail,
+    /// including the moves vetoed by `MoveValidator`s.
+    #[allow(clippy::too_many_arguments)]
+    pub fn check_transfer(&self, from_collection: &str, from_index: UVec2, to_collection: &str, to_index: UVec2, items: &Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        let from_item = *self.get(from_collection, &from_index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: from_collection.to_string(), index: from_index })?;
+
+        // both items are moved away, so their cells should not be considered occupied
+        let same_collection = from_collection == to_collection;
+        let ignored = |index: UVec2, other: UVec2| if same_collection { vec![index, other] } else { vec![index] };
+
+        self.check_fits(to_collection, to_index, from_item, items, item_types, &ignored(to_index, from_index))?;
+        let into_item = self.get(to_collection, &to_index).copied().filter(|into_item| *into_item != from_item);
+        if let Some(into_item) = into_item {
+            self.check_fits(from_collection, from_index, into_item, items, item_types, &ignored(from_index, to_index))?;
+        }
+
+        if !same_collection {
+            let moved_weight = match into_item {
+                // only the part that fits into the other stack is moved
+                Some(into_item) if items.can_merge(from_item, into_item, item_types) => {
+                    let from_meta = items.try_get_item_meta(from_item, item_types)?;
+                    let into_meta = items.try_get_item_meta(into_item, item_types)?;
+                    from_meta.stack_size.min(into_meta.max_stack_size - into_meta.stack_size) as f32 * from_meta.weight
+                },
+                Some(into_item) => items.weight(from_item, item_types) - items.weight(into_item, item_types),
+                None => items.weight(from_item, item_types),
+            };
+            self.check_weight(to_collection, from_item, moved_weight, items, item_types)?;
+            self.check_weight(from_collection, into_item.unwrap_or(from_item), -moved_weight, items, item_types)?;
+        }
+
+        validators.check(&ItemMove {
+            item: from_item,
+            from: Some(handle(from_collection, from_index)),
+            to: Some(handle(to_collection, to_index)),
+            replaced: into_item,
+        }, self, items, item_types)
+    }
+
+    /// Moves the item anchored at `from_index` to `to_index`, possibly in another collection.
+    /// If there is an item anchored at `to_index`, the stacks are merged, or the items are swapped.
+    /// Both items must fit into their new positions, including the tags required there.
+    #[allow(clippy::too_many_arguments)]
+    pub fn transfer(&mut self, from_collection: &str, from_index: UVec2, to_collection: &str, to_index: UVec2, items: &mut Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        self.transfer_with_reason(from_collection, from_index, to_collection, to_index, items, item_types, validators, ChangeReason::Transfer)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn transfer_with_reason(
+        &mut self,
+        from_collection: &str,
+        from_index: UVec2,
+        to_collection: &str,
+        to_index: UVec2,
+        items: &mut Items,
+        item_types: &ItemTypes,
+        validators: &MoveValidators,
+        reason: ChangeReason,
+    ) -> Result<(), SiminvError> {
+        if from_collection == to_collection && from_index == to_index {
+            return Ok(())
+        }
+        self.check_transfer(from_collection, from_index, to_collection, to_index, items, item_types, validators)?;
+
+        let from_item = *self.get(from_collection, &from_index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: from_collection.to_string(), index: from_index })?;
+        let into_item = self.get(to_collection, &to_index).copied();
+        let (new_from, new_into) = match into_item {
+            Some(into_item) => items.merge_or_swap(from_item, into_item, item_types)?,
+            None => (None, Some(from_item)),
+        };
+        // the stack stayed in place, so the other one has been merged into it
+        let into_reason = if into_item.is_some() && new_into == into_item { ChangeReason::Merge } else { reason };
+
+        // remove both first, so a swapped multi cell item does not clear the other one
+        self.remove_unregistered(from_collection, from_index);
+        self.remove_unregistered(to_collection, to_index);
+        for (collection, index, old, item, reason) in [
+            (from_collection, from_index, Some(from_item), new_from, reason),
+            (to_collection, to_index, into_item, new_into, into_reason),
+        ] {
+            if let Some(item) = item {
+                let (shape, _) = item_shape_and_tags(item, items, item_types)?;
+                self.set_unregistered(collection, index, item, shape);
+            }
+            self.record_change(collection, index, old, reason);
+        }
+        Ok(())
+    }
+
+    /// Splits `amount` items off the stack anchored at `from_index` and puts them at `to_index`,
+    /// which has to be empty, or hold a stack they can be merged with.
+    /// Items that do not fit into the other stack return to the source stack.
+    #[allow(clippy::too_many_arguments)]
+    pub fn split(
+        &mut self,
+        from_collection: &str,
+        from_index: UVec2,
+        to_collection: &str,
+        to_index: UVec2,
+        amount: u64,
+        items: &mut Items,
+        item_types: &ItemTypes,
+        validators: &MoveValidators,
+    ) -> Result<(), SiminvError> {
+        let from_item = *self.get(from_collection, &from_index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: from_collection.to_string(), index: from_index })?;
+
+        self.transaction(items, |inventory, items| {
+            let split = items.split(from_item, amount)?;
+            let into_item = inventory.get(to_collection, &to_index).copied();
+            let moved_weight = match into_item {
+                Some(into_item) if items.can_merge(split, into_item, item_types) => {
+                    let into_meta = items.try_get_item_meta(into_item, item_types)?;
+                    amount.min(into_meta.max_stack_size - into_meta.stack_size) as f32 * into_meta.weight
+                },
+                Some(_) => return Err(SiminvError::IndexOccupied { collection: to_collection.to_string(), index: to_index }),
+                None => {
+                    inventory.check_fits(to_collection, to_index, split, items, item_types, &[])?;
+                    items.weight(split, item_types)
+                },
+            };
+            if from_collection != to_collection {
+                inventory.check_weight(to_collection, split, moved_weight, items, item_types)?;
+            }
+            validators.check(&ItemMove {
+                item: split,
+                from: Some(handle(from_collection, from_index)),
+                to: Some(handle(to_collection, to_index)),
+                replaced: into_item,
+            }, inventory, items, item_types)?;
+
+            match into_item {
+                Some(into_item) => {
+                    let (rest, _) = items.merge_or_swap(split, into_item, item_types)?;
+                    if let Some(rest) = rest {
+                        items.merge_or_swap(rest, from_item, item_types)?;
+                    }
+                    inventory.record_change(to_collection, to_index, Some(into_item), ChangeReason::Merge);
+                },
+                None => {
+                    let (shape, _) = item_shape_and_tags(split, items, item_types)?;
+                    inventory.set_unregistered(to_collection, to_index, split, shape);
+                    inventory.record_change(to_collection, to_index, None, ChangeReason::Split);
+                },
+            }
+            inventory.record_change(from_collection, from_index, Some(from_item), ChangeReason::Split);
+            Ok(())
+        })
+    }
+
+    /// Moves the item anchored at `from_index` into `to_collection`, into the indexes chosen by the `policy`.
+    /// Whatever does not fit stays at `from_index`. If any of the moves fails, none of them is made.
+    /// Returns the indexes the items have been moved to.
+    #[allow(clippy::too_many_arguments)]
+    pub fn quick_move(&mut self, from_collection: &str, from_index: UVec2, to_collection: &str, policy: &dyn MovePolicy, items: &mut Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<Vec<UVec2>, SiminvError> {
+        let item = *self.get(from_collection, &from_index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: from_collection.to_string(), index: from_index })?;
+        let candidates = self.move_candidates(from_collection, from_index, to_collection, items, item_types, validators)?;
+
+        let targets = policy.targets(item, &candidates, items, item_types);
+        if targets.is_empty() {
+            return Err(SiminvError::NoMatchingSlot(item))
+        }
+
+        // a failing move rolls back the ones made before it
+        self.transaction(items, |inventory, items| {
+            let mut moved = Vec::new();
+            for target in targets {
+                let Some(candidate) = candidates.get(target) else { continue };
+                inventory.transfer_with_reason(from_collection, from_index, to_collection, candidate.index, items, item_types, validators, ChangeReason::AutoMove)?;
+                moved.push(candidate.index);
+
+                // the whole stack has left the source
+                if inventory.get(from_collection, &from_index) != Some(&item) {
+                    break
+                }
+            }
+            Ok(moved)
+        })
+    }
+
+    // indexes of `to_collection` that the item can be transferred to, row after row
+    fn move_candidates(&self, from_collection: &str, from_index: UVec2, to_collection: &str, items: &Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<Vec<MoveCandidate>, SiminvError> {
+        let item = *self.get(from_collection, &from_index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: from_collection.to_string(), index: from_index })?;
+        let (shape, tags) = item_shape_and_tags(item, items, item_types)?;
+
+        let occupied = self.sorted_anchors(to_collection)
+            .into_iter()
+            .filter(|index| from_collection != to_collection || *index != from_index);
+        let empty = self.collection_or_default(to_collection).free_anchors(shape, tags);
+
+        let mut candidates = occupied
+            .chain(empty)
+            .filter(|index| self.check_transfer(from_collection, from_index, to_collection, *index, items, item_types, validators).is_ok())
+            .map(|index| MoveCandidate { index, item: self.get(to_collection, &index).copied() })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|candidate| (candidate.index.y, candidate.index.x));
+        Ok(candidates)
+    }
+
+    // anchors of the collection, row after row
+    fn sorted_anchors(&self, collection: &str) -> Vec<UVec2> {
+        let mut anchors = self.collection_or_default(collection).by_index.keys().copied().collect::<Vec<_>>();
+        anchors.sort_by_key(|index| (index.y, index.x));
+        anchors
+    }
+
+    /// Consumes `amount` items from the stack at `index` and returns the number of remaining items.
+    /// Depleted stacks are removed from both the inventory and `items`.
+    pub fn consume(&mut self, collection: &str, index: UVec2, amount: u64, items: &mut Items) -> Result<u64, SiminvError> {
+        let item = *self.get(collection, &index)
+            .ok_or_else(|| SiminvError::EmptyIndex { collection: collection.to_string(), index })?;
+
+        let remaining = items.consume(item, amount)?;
+        if remaining == 0 {
+            self.remove(collection, index)?;
+        } else {
+            // stack size changed
+            self.record_change(collection, index, Some(item), ChangeReason::Remove);
+        }
+        Ok(remaining)
+    }
+
+    /// Merges partial stacks and moves all items of the collection to the first indexes, ordered by the `key`.
+    /// Blocked indexes and the tags required at indexes are respected, items that are not allowed to move stay in place.
+    /// If the sorted items do not fit, e.g. because of their shapes, the collection is left untouched.
+    pub fn sort_collection(&mut self, collection: &str, key: SortKey, items: &mut Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        self.transaction(items, |inventory, items| {
+            inventory.merge_partial_stacks(collection, collection, ChangeReason::Sort, items, item_types, validators)?;
+
+            let before = inventory.collection_or_default(collection).by_index.clone();
+            let mut sorted = inventory.sorted_anchors(collection)
+                .into_iter()
+                .filter_map(|index| before.get(&index).map(|item| (index, *item)))
+                .filter(|(index, item)| inventory.check_move_within(collection, *index, *item, items, item_types, validators))
+                .collect::<Vec<_>>();
+            // items of unregistered types go last
+            sorted.sort_by(|(_, a), (_, b)| match (items.get_item_meta(*a, item_types), items.get_item_meta(*b, item_types)) {
+                (Some(a), Some(b)) => key.compare(&a, &b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            });
+
+            // everything is placed again, so the items do not block each other
+            for (index, _) in &sorted {
+                inventory.remove_unregistered(collection, *index);
+            }
+            for (_, item) in &sorted {
+                let (shape, tags) = item_shape_and_tags(*item, items, item_types)?;
+                let index = inventory.collection_or_default(collection)
+                    .find_free(shape, tags)
+                    .ok_or_else(|| SiminvError::CollectionFull(collection.to_string()))?;
+                inventory.set_unregistered(collection, index, *item, shape);
+            }
+
+            // the ui is updated only where the items have changed
+            let after = inventory.collection_or_default(collection).by_index.clone();
+            let mut changed = before.keys().chain(after.keys())
+                .filter(|index| before.get(*index) != after.get(*index))
+                .copied()
+                .collect::<Vec<_>>();
+            changed.sort_by_key(|index| (index.y, index.x));
+            changed.dedup();
+            for index in changed {
+                inventory.record_change(collection, index, before.get(&index).copied(), ChangeReason::Sort);
+            }
+            Ok(())
+        })
+    }
+
+    /// Merges partial stacks of every item type into as few stacks as possible, row after row, freeing their indexes.
+    /// Stacks are merged with `Items::merge_or_swap` semantics, so stacks with incompatible properties stay apart.
+    pub fn stack_all(&mut self, collection: &str, items: &mut Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        self.transaction(items, |inventory, items| {
+            inventory.merge_partial_stacks(collection, collection, ChangeReason::Stack, items, item_types, validators)
+        })
+    }
+
+    /// Merges partial stacks of `from_collection` into the partial stacks of the same type in `to_collection`,
+    /// e.g. to quick-stack the loot into the stash. Items without a matching stack in `to_collection` stay in place.
+    pub fn quick_stack(&mut self, from_collection: &str, to_collection: &str, items: &mut Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        self.transaction(items, |inventory, items| {
+            inventory.merge_partial_stacks(from_collection, to_collection, ChangeReason::Stack, items, item_types, validators)
+        })
+    }
+
+    // returns true if validators allow moving the item anywhere within its collection
+    fn check_move_within(&self, collection: &str, index: UVec2, item: ItemId, items: &Items, item_types: &ItemTypes, validators: &MoveValidators) -> bool {
+        validators.check(&ItemMove {
+            item,
+            from: Some(handle(collection, index)),
+            to: Some(handle(collection, index)),
+            replaced: None,
+        }, self, items, item_types).is_ok()
+    }
+
+    // merges partial stacks of `from_collection` into the first partial stacks of the same type in `into_collection`, row after row
+    #[allow(clippy::too_many_arguments)]
+    fn merge_partial_stacks(&mut self, from_collection: &str, into_collection: &str, reason: ChangeReason, items: &mut Items, item_types: &ItemTypes, validators: &MoveValidators) -> Result<(), SiminvError> {
+        let into_anchors = self.sorted_anchors(into_collection);
+        for from_index in self.sorted_anchors(from_collection) {
+            for into_index in &into_anchors {
+                // within one collection, stacks are merged only into the previous ones
+                if from_collection == into_collection && (into_index.y, into_index.x) >= (from_index.y, from_index.x) {
+                    break
+                }
+                let (Some(from), Some(into)) = (self.get(from_collection, &from_index).copied(), self.get(into_collection, into_index).copied()) else { continue };
+                // stacks that are not allowed to merge, e.g. by validators or weight limits, are skipped
+                if !items.can_merge(from, into, item_types) || self.check_transfer(from_collection, from_index, into_collection, *into_index, items, item_types, validators).is_err() {
+                    continue
+                }
+
+                let (rest, _) = items.merge_or_swap(from, into, item_types)?;
+                self.record_change(into_collection, *into_index, Some(into), ChangeReason::Merge);
+                if rest.is_none() {
+                    self.remove_unregistered(from_collection, from_index);
+                }
+                self.record_change(from_collection, from_index, Some(from), reason);
+                if rest.is_none() {
+                    break
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs multi-step operations, like crafting or trading, as one transaction.
+    /// If any step fails, the items in the inventory and `items` are restored and the error is returned.
+    /// Otherwise, the ui is updated with one batch of changes.
+    /// Collection settings, e.g. `set_max_size`, are not restored.
+    pub fn transaction<T>(
+        &mut self,
+        items: &mut Items,
+        steps: impl FnOnce(&mut Inventory, &mut Items) -> Result<T, SiminvError>,
+    ) -> Result<T, SiminvError> {
+        // only the changed entries are remembered, so they can be put back
+        let checkpoint = self.checkpoint();
+        let items_checkpoint = items.checkpoint();
+
+        match steps(self, items) {
+            Ok(result) => {
+                // every index is updated once, even if it has changed in several steps
+                let mut modified = self.modified.split_off(checkpoint.modified);
+                modified.sort_by(|(a, a_index), (b, b_index)| (a, a_index.y, a_index.x).cmp(&(b, b_index.y, b_index.x)));
+                modified.dedup();
+                self.modified.extend(modified);
+                self.commit(checkpoint);
+                items.commit(items_checkpoint);
+                Ok(result)
+            },
+            Err(error) => {
+                self.rollback(checkpoint);
+                items.rollback(items_checkpoint);
+                Err(error)
+            },
+        }
+    }
+
+    // starts a new undo log and journal for a transaction
+    fn checkpoint(&mut self) -> Checkpoint {
+        Checkpoint {
+            undo: self.undo.replace(Vec::new()),
+            journal: core::mem::take(&mut self.journal),
+            modified: self.modified.len(),
+            changes: self.changes.len(),
+        }
+    }
 
-        if let Some(added) = maybe_added {
-            self.modified.push((collection.to_string(), added));
+    // keeps the changes made since the checkpoint, an outer transaction can still undo them
+    fn commit(&mut self, checkpoint: Checkpoint) {
+        let undo = core::mem::replace(&mut self.undo, checkpoint.undo).unwrap_or_default();
+        if let Some(outer) = &mut self.undo {
+            outer.extend(undo);
+        }
+        let journal = core::mem::replace(&mut self.journal, checkpoint.journal);
+        for (key, old) in journal {
+            self.journal.entry(key).or_insert(old);
         }
+    }
+
+    // undoes the changes made since the checkpoint
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        let undo = core::mem::replace(&mut self.undo, checkpoint.undo).unwrap_or_default();
+        for undo in undo.into_iter().rev() {
+            match undo {
+                Undo::Collection(name) => {
+                    self.collections_by_name.remove(&name);
+                },
+                Undo::Anchor(name, snapshot) => if let Some(collection) = self.collections_by_name.get_mut(&name) {
+                    collection.restore(snapshot);
+                },
+            }
+        }
+        self.journal = checkpoint.journal;
+        self.modified.truncate(checkpoint.modified);
+        self.changes.truncate(checkpoint.changes);
+    }
+
+    /// Returns true if there are no items in any of the collections.
+    pub fn is_empty(&self) -> bool {
+        self.collections_by_name.values().all(|collection| collection.by_index.is_empty())
+    }
+
+    /// Names of all collections, in no particular order.
+    pub fn collections(&self) -> impl Iterator<Item = &str> {
+        self.collections_by_name.keys().map(String::as_str)
+    }
+
+    /// Every item of the collection, or of all collections if `collection` is `None`,
+    /// with its collection, anchor index and meta. Items of unregistered types are skipped.
+    pub fn iter<'a>(&'a self, collection: Option<&'a str>, items: &'a Items, item_types: &'a ItemTypes) -> impl Iterator<Item = (&'a str, UVec2, ItemId, ItemMeta<'a>)> {
+        self.collections_by_name
+            .iter()
+            .filter(move |(name, _)| collection.is_none_or(|collection| collection == name.as_str()))
+            .flat_map(|(name, collection)| collection.by_index.iter().map(move |(index, item)| (name.as_str(), *index, *item)))
+            .filter_map(|(name, index, item)| items.get_item_meta(item, item_types).map(|meta| (name, index, item, meta)))
+    }
 
-        maybe_added
+    /// Number of items of the type in all stacks of the collection, or of all collections if `collection` is `None`.
+    pub fn count(&self, type_name: &str, collection: Option<&str>, items: &Items) -> u64 {
+        self.find_items(collection, items, |item| item.type_name == type_name)
+            .iter()
+            .filter_map(|(_, item)| items.get_item(*item))
+            .map(|item| item.stack_size)
+            .sum()
+    }
+
+    /// Returns true if there are at least `count` items of the type, e.g. to check quest requirements.
+    pub fn contains(&self, type_name: &str, count: u64, collection: Option<&str>, items: &Items) -> bool {
+        self.count(type_name, collection, items) >= count
+    }
+
+    /// Indexes holding items of the type, row after row in every collection.
+    pub fn find_by_type(&self, type_name: &str, collection: Option<&str>, items: &Items) -> Vec<InventoryHandle> {
+        self.find_items(collection, items, |item| item.type_name == type_name)
+            .into_iter()
+            .map(|(handle, _)| handle)
+            .collect()
+    }
+
+    /// Indexes holding items with the tag, or any of its child tags, row after row in every collection.
+    pub fn find_by_tag(&self, tag: &Tag, collection: Option<&str>, items: &Items, item_types: &ItemTypes) -> Vec<InventoryHandle> {
+        self.find_items(collection, items, |item| item_types.get_tags_with_type_name(&item.type_name).is_some_and(|tags| tags.contains(tag)))
+            .into_iter()
+            .map(|(handle, _)| handle)
+            .collect()
+    }
+
+    // anchors of the items passing the predicate, sorted by collection name, then row after row
+    fn find_items(&self, collection: Option<&str>, items: &Items, predicate: impl Fn(&Item) -> bool) -> Vec<(InventoryHandle, ItemId)> {
+        let mut names = self.collections()
+            .filter(|name| collection.is_none_or(|collection| collection == *name))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.into_iter()
+            .flat_map(|name| self.sorted_anchors(name)
+                .into_iter()
+                .filter_map(move |index| self.get(name, &index).map(|item| (handle(name, index), *item))))
+            .filter(|(_, item)| items.get_item(*item).is_some_and(&predicate))
+            .collect()
+    }
+
+    /// First index, row after row, that is not covered by any item and is not blocked.
+    pub fn first_free_index(&self, collection: &str) -> Option<UVec2> {
+        self.collection_or_default(collection).free_cells().next()
+    }
+
+    /// Number of indexes that are not covered by any item and are not blocked.
+    pub fn free_slot_count(&self, collection: &str) -> u64 {
+        let collection = self.collection_or_default(collection);
+        let in_bounds = |index: &UVec2| index.x < collection.max_size.x && index.y < collection.max_size.y;
+        let blocked = collection.blocked.iter().filter(|index| in_bounds(index)).count() as u64;
+        let occupied = collection.occupied.keys().filter(|index| in_bounds(index) && !collection.blocked.contains(*index)).count() as u64;
+        collection.max_size.x as u64 * collection.max_size.y as u64 - blocked - occupied
     }
 
     pub(crate) fn take_modified(&mut self) -> Vec<(String, UVec2)> {
@@ -67,55 +783,275 @@ impl Inventory {
         core::mem::swap(&mut self.modified, &mut result);
         result
     }
+
+    pub(crate) fn take_changes(&mut self) -> Vec<InventoryChanged> {
+        core::mem::take(&mut self.changes)
+    }
+
+    pub(crate) fn take_journal(&mut self) -> HashMap<(String, UVec2), Option<ItemId>> {
+        core::mem::take(&mut self.journal)
+    }
+
+    // puts the items back at the indexes without recording it in the journal
+    pub(crate) fn restore(&mut self, states: &[(String, UVec2, Option<ItemId>)], items: &Items, item_types: &ItemTypes) {
+        let old = states.iter()
+            .map(|(collection, index, _)| self.remove_unregistered(collection, *index))
+            .collect::<Vec<_>>();
+
+        for ((collection, index, item), old) in states.iter().zip(old) {
+            if let Some(item) = item {
+                let shape = item_shape_and_tags(*item, items, item_types).map(|(shape, _)| shape.clone()).unwrap_or_default();
+                self.set_unregistered(collection, *index, *item, &shape);
+            }
+            self.modified.push((collection.clone(), *index));
+            self.changes.push(InventoryChanged {
+                collection: collection.clone(),
+                index: *index,
+                old,
+                new: *item,
+                reason: ChangeReason::History,
+            });
+        }
+    }
+
+    // marks indexes holding the items to be updated by the ui, e.g. when their stack size changes
+    pub(crate) fn mark_modified(&mut self, changed: &[ItemId]) {
+        for (name, collection) in &self.collections_by_name {
+            for (index, item) in &collection.by_index {
+                if changed.contains(item) {
+                    self.modified.push((name.clone(), *index));
+                }
+            }
+        }
+    }
+
+    // marks the index to be updated by the ui and reports the change
+    fn record_change(&mut self, collection: &str, index: UVec2, old: Option<ItemId>, reason: ChangeReason) {
+        self.journal.entry((collection.to_string(), index)).or_insert(old);
+        self.modified.push((collection.to_string(), index));
+        self.changes.push(InventoryChanged {
+            collection: collection.to_string(),
+            index,
+            old,
+            new: self.get(collection, &index).copied(),
+            reason,
+        });
+    }
+}
+
+impl ItemHolder for Inventory {
+    fn held_items(&self) -> Vec<ItemId> {
+        self.collections_by_name
+            .values()
+            .flat_map(|collection| collection.by_index.values().copied())
+            .collect()
+    }
+}
+
+fn handle(collection: &str, index: UVec2) -> InventoryHandle {
+    InventoryHandle { collection: collection.to_string(), index }
+}
+
+// items of unregistered types are treated as single cell items without tags
+fn item_shape_and_tags<'a>(item: ItemId, items: &Items, item_types: &'a ItemTypes) -> Result<(&'a ItemShape, &'a [Tag]), SiminvError> {
+    static SINGLE: ItemShape = ItemShape::Single;
+    let item = items.get_item(item).ok_or(SiminvError::UnknownItemId(item))?;
+    Ok(item_types.get_item_type_with_type_name(&item.type_name)
+        .map(|item_type| (&item_type.shape, item_types.get_tags_with_type_name(&item.type_name).unwrap_or_default()))
+        .unwrap_or((&SINGLE, &[])))
+}
+
+// state to return to if a transaction fails
+struct Checkpoint {
+    undo: Option<Vec<Undo>>,
+    journal: HashMap<(String, UVec2), Option<ItemId>>,
+    modified: usize,
+    changes: usize,
+}
+
+#[derive(Clone)]
+enum Undo {
+    // the collection did not exist before
+    Collection(String),
+    Anchor(String, Snapshot),
+}
+
+// an anchor of a collection and the cells that setting or removing the anchor may overwrite
+#[derive(Clone)]
+struct Snapshot {
+    index: UVec2,
+    item: Option<ItemId>,
+    cells: Vec<(UVec2, Option<UVec2>)>,
+}
+
+// reason why an item does not fit into a collection
+enum Misfit {
+    OutOfBounds,
+    Occupied,
+    TagMismatch,
+    Rejected,
+}
+
+impl Misfit {
+    fn into_error(self, collection: &str, index: UVec2, item: ItemId) -> SiminvError {
+        match self {
+            Misfit::OutOfBounds => SiminvError::IndexOutOfBounds { collection: collection.to_string(), index },
+            Misfit::Occupied => SiminvError::IndexOccupied { collection: collection.to_string(), index },
+            Misfit::TagMismatch => SiminvError::TagMismatch(item),
+            Misfit::Rejected => SiminvError::RejectedByCollection { collection: collection.to_string(), item },
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct InventoryCollection {
+    // items by their anchor index
     by_index: HashMap<UVec2, ItemId>,
+    // anchor index of every occupied cell
+    occupied: HashMap<UVec2, UVec2>,
     max_size: UVec2,
+    // tags required from items covering the index
+    // set by the slots, so they are not saved with the items
+    #[serde(skip)]
+    required_tags: HashMap<UVec2, TagRule>,
+    // indexes that can't be covered by any item
+    // set by the grid layout, so they are not saved with the items
+    #[serde(skip)]
+    blocked: HashSet<UVec2>,
+    // tags required from every item in the collection
+    #[serde(default)]
+    filter: Option<TagRule>,
+    #[serde(default)]
+    max_weight: Option<f32>,
 }
 
 impl Default for InventoryCollection {
     fn default() -> Self {
         Self {
             by_index: HashMap::default(),
+            occupied: HashMap::default(),
             // inserts everything into 1 row
             max_size: UVec2::new(u32::MAX, 1),
+            required_tags: HashMap::default(),
+            blocked: HashSet::default(),
+            filter: None,
+            max_weight: None,
         }
     }
 }
 
 impl InventoryCollection {
-    fn set(&mut self, index: UVec2, item: ItemId) {
+    // overwrites cells of other items without any checks
+    // callers are expected to validate the placement with `fits` first
+    fn set(&mut self, index: UVec2, item: ItemId, shape: &ItemShape) {
+        self.remove(&index);
+        for cell in shape.cells_at(index).unwrap_or_else(|| vec![index]) {
+            self.occupied.insert(cell, index);
+        }
         self.by_index.insert(index, item);
     }
 
     fn get(&self, index: &UVec2) -> Option<&ItemId> {
         self.by_index.get(index)
     }
-    
-    fn get_mut(&mut self, index: &UVec2) -> Option<&mut ItemId> {
-        self.by_index.get_mut(index)
+
+    fn get_occupant(&self, index: &UVec2) -> Option<(UVec2, ItemId)> {
+        let anchor = self.occupied.get(index)?;
+        self.by_index.get(anchor).map(|item| (*anchor, *item))
     }
 
-    fn remove(&mut self, index: &UVec2) {
-        self.by_index.remove(index);
+    fn remove(&mut self, index: &UVec2) -> Option<ItemId> {
+        let removed = self.by_index.remove(index)?;
+        self.occupied.retain(|_, anchor| anchor != index);
+        Some(removed)
     }
 
-    // insert into first available slot
-    // returns None if the collection is full
-    // this may take a while?
-    fn add(&mut self, item: ItemId) -> Option<UVec2> {
-        for y in 0..self.max_size.y {
-            for x in 0..self.max_size.x {
-                let index = UVec2::new(x, y);
-                if !self.by_index.contains_key(&index) {
-                    self.by_index.insert(index, item);
-                    return Some(index)
-                }
-            }
+    // `shape` of the item that is going to be set, `None` if the anchor is going to be removed
+    fn snapshot(&self, index: UVec2, shape: Option<&ItemShape>) -> Snapshot {
+        let covered = self.occupied.iter()
+            .filter(|(_, anchor)| **anchor == index)
+            .map(|(cell, _)| *cell);
+        let set = shape.map(|shape| shape.cells_at(index).unwrap_or_else(|| vec![index])).unwrap_or_default();
+        Snapshot {
+            index,
+            item: self.by_index.get(&index).copied(),
+            cells: covered.chain(set).map(|cell| (cell, self.occupied.get(&cell).copied())).collect(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        match snapshot.item {
+            Some(item) => self.by_index.insert(snapshot.index, item),
+            None => self.by_index.remove(&snapshot.index),
+        };
+        for (cell, anchor) in snapshot.cells {
+            match anchor {
+                Some(anchor) => self.occupied.insert(cell, anchor),
+                None => self.occupied.remove(&cell),
+            };
         }
-        None
+    }
+
+    fn fits(&self, index: UVec2, shape: &ItemShape, tags: &[Tag], ignored: &[UVec2]) -> Result<(), Misfit> {
+        let cells = shape.cells_at(index).ok_or(Misfit::OutOfBounds)?;
+        if cells.iter().any(|cell| cell.x >= self.max_size.x || cell.y >= self.max_size.y || self.blocked.contains(cell)) {
+            return Err(Misfit::OutOfBounds)
+        }
+
+        if !self.accepts(tags) {
+            return Err(Misfit::Rejected)
+        }
+
+        if cells.iter().any(|cell| self.required_tags.get(cell).is_some_and(|rule| !rule.matches(tags))) {
+            return Err(Misfit::TagMismatch)
+        }
+
+        if cells.iter().any(|cell| self.occupied.get(cell).is_some_and(|anchor| !ignored.contains(anchor))) {
+            return Err(Misfit::Occupied)
+        }
+
+        Ok(())
+    }
+
+    fn accepts(&self, tags: &[Tag]) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.matches(tags))
+    }
+
+    // first anchor, row after row, where the item fits
+    fn find_free(&self, shape: &ItemShape, tags: &[Tag]) -> Option<UVec2> {
+        self.anchors_to_check(shape).find(|index| self.fits(*index, shape, tags, &[]).is_ok())
+    }
+
+    // every anchor where the item fits
+    fn free_anchors(&self, shape: &ItemShape, tags: &[Tag]) -> Vec<UVec2> {
+        self.anchors_to_check(shape).filter(|index| self.fits(*index, shape, tags, &[]).is_ok()).collect()
+    }
+
+    // cells that are not covered and not blocked, row after row
+    fn free_cells(&self) -> impl Iterator<Item = UVec2> {
+        self.anchors_to_check(&ItemShape::Single)
+            .filter(|index| !self.blocked.contains(index) && !self.occupied.contains_key(index))
+    }
+
+    fn anchors_to_check(&self, shape: &ItemShape) -> impl Iterator<Item = UVec2> {
+        let size = shape.size();
+        let used = self.occupied.keys()
+            .chain(self.required_tags.keys())
+            .chain(self.blocked.iter())
+            .fold(UVec2::ZERO, |used, index| used.max(index.saturating_add(UVec2::ONE)));
+        let max_anchor = |max_size: u32, size: u32, used: u32| {
+            // do not check anchors that would leave the collection anyway
+            let max_anchor = max_size.saturating_sub(size - 1);
+            // anchors past every used index of an unbounded axis are all the same,
+            // so there is no need to check more than one of them
+            match max_size {
+                u32::MAX => max_anchor.min(used.saturating_add(1)),
+                _ => max_anchor,
+            }
+        };
+        let max_x = max_anchor(self.max_size.x, size.x, used.x);
+        let max_y = max_anchor(self.max_size.y, size.y, used.y);
+        (0..max_y).flat_map(move |y| (0..max_x).map(move |x| UVec2::new(x, y)))
     }
 }
 
@@ -123,31 +1059,593 @@ pub(crate) fn on_slot_update(
     update: On<SlotEvent<SlotUpdate>>,
     query: Query<(&Slot, &InventoryHandle)>,
     mut inventory: ResMut<Inventory>,
+    items: Res<Items>,
+    item_types: Res<ItemTypes>,
 ) {
     let Ok((slot, inventory_handle)) = query.get(update.entity) else { return };
+    let old = inventory.get(&inventory_handle.collection, &inventory_handle.index).copied();
     match slot.item {
         Some(item_id) => {
-            inventory.set_unregistered(&inventory_handle.collection, inventory_handle.index, item_id);
+            let shape = item_shape_and_tags(item_id, &items, &item_types).map(|(shape, _)| shape.clone()).unwrap_or_default();
+            inventory.set_unregistered(&inventory_handle.collection, inventory_handle.index, item_id, &shape);
         },
         None => {
             inventory.remove_unregistered(&inventory_handle.collection, inventory_handle.index);
         }
     }
+
+    // the slot has been changed without the inventory, e.g. by dropping an item from a slot outside of it
+    if old != slot.item {
+        inventory.journal.entry((inventory_handle.collection.clone(), inventory_handle.index)).or_insert(old);
+        inventory.changes.push(InventoryChanged {
+            collection: inventory_handle.collection.clone(),
+            index: inventory_handle.index,
+            old,
+            new: slot.item,
+            reason: ChangeReason::Slot,
+        });
+    }
+}
+
+pub(crate) fn write_inventory_changes(
+    mut inventory: ResMut<Inventory>,
+    mut changed: MessageWriter<InventoryChanged>,
+) {
+    changed.write_batch(inventory.bypass_change_detection().take_changes());
 }
 
 #[cfg(test)]
 mod test {
-    use crate::item::Items;
+    use crate::{auto_move::{EmptyOrReplace, FillFromBottom, MergeFirst, OnlyEmpty}, item::{ItemType, ItemTypes, ItemValidation, Items}};
     use super::*;
 
     #[test]
     fn test_inventory() {
+        let validators = MoveValidators::default();
+        let item_types = ItemTypes::with_validation(ItemValidation::Lenient);
         let mut items = Items::default();
-        let sword = items.add_item("sword");
+        let sword = items.add_item("sword", &item_types).unwrap();
 
 
         let mut inventory = Inventory::default();
-        inventory.add("main", sword);
+        assert_eq!(inventory.add("main", sword, &items, &item_types, &validators), Ok(UVec2::new(0, 0)));
+        assert_eq!(inventory.remove("main", UVec2::new(0, 0)), Ok(sword));
+        assert_eq!(inventory.remove("main", UVec2::new(0, 0)), Err(SiminvError::EmptyIndex {
+            collection: "main".into(),
+            index: UVec2::new(0, 0),
+        }));
+        assert_eq!(inventory.add("main", ItemId::from(100), &items, &item_types, &validators), Err(SiminvError::UnknownItemId(ItemId::from(100))));
     }
-}
 
+    #[test]
+    fn test_inventory_consume() {
+        let validators = MoveValidators::default();
+        let item_types = ItemTypes::with_validation(ItemValidation::Lenient);
+        let mut items = Items::default();
+        let stones = items.add_items("stones", 5, &item_types).unwrap()[0];
+        let lost = items.add_item("sword", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        let index = inventory.add("main", stones, &items, &item_types, &validators).unwrap();
+        assert_eq!(items.find_orphans(&[&inventory]), vec![lost]);
+
+        assert_eq!(inventory.consume("main", index, 3, &mut items), Ok(2));
+        assert_eq!(inventory.consume("main", index, 2, &mut items), Ok(0));
+        assert_eq!(inventory.get("main", &index), None);
+        assert!(items.get_item(stones).is_none());
+    }
+
+    #[test]
+    fn test_inventory_item_shapes() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::with_validation(ItemValidation::Lenient);
+        let mut items = Items::default();
+        item_types.register_item_type(ItemType {
+            type_name: "armor".into(),
+            display_name: "Armor".into(),
+            max_stack_size: 1,
+            shape: ItemShape::rect(2, 2),
+            ..default()
+        });
+        let armor = items.add_item("armor", &item_types).unwrap();
+        let sword = items.add_item("sword", &item_types).unwrap();
+        let bow = items.add_item("bow", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("main", UVec2::new(3, 2));
+        assert_eq!(inventory.add("main", sword, &items, &item_types, &validators), Ok(UVec2::new(0, 0)));
+        // armor does not fit at (0, 0), nor at (2, 0)
+        assert_eq!(inventory.add("main", armor, &items, &item_types, &validators), Ok(UVec2::new(1, 0)));
+        assert_eq!(inventory.get_occupant("main", &UVec2::new(2, 1)), Some((UVec2::new(1, 0), armor)));
+
+        // overlaps the armor
+        assert_eq!(inventory.set("main", UVec2::new(2, 1), bow, &items, &item_types, &validators), Err(SiminvError::IndexOccupied {
+            collection: "main".into(),
+            index: UVec2::new(2, 1),
+        }));
+        // leaves the collection
+        assert_eq!(inventory.set("main", UVec2::new(3, 0), bow, &items, &item_types, &validators), Err(SiminvError::IndexOutOfBounds {
+            collection: "main".into(),
+            index: UVec2::new(3, 0),
+        }));
+        assert_eq!(inventory.add("main", bow, &items, &item_types, &validators), Ok(UVec2::new(0, 1)));
+        assert_eq!(inventory.add("main", items.add_item("sword", &item_types).unwrap(), &items, &item_types, &validators), Err(SiminvError::CollectionFull("main".into())));
+
+        assert_eq!(inventory.remove("main", UVec2::new(1, 0)), Ok(armor));
+        assert_eq!(inventory.get_occupant("main", &UVec2::new(2, 1)), None);
+    }
+
+    #[test]
+    fn test_inventory_layout_is_not_saved() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "sword".into(),
+            display_name: "Sword".into(),
+            max_stack_size: 1,
+            ..default()
+        });
+        let mut items = Items::default();
+        let sword = items.add_item("sword", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("main", UVec2::new(3, 1));
+        inventory.set_blocked("main", UVec2::new(0, 0), true);
+        inventory.set_required_tags("main", UVec2::new(1, 0), Some(TagRule::AnyOf(vec![Tag("ring".into())])));
+        assert_eq!(inventory.add("main", sword, &items, &item_types, &validators), Ok(UVec2::new(2, 0)));
+
+        let saved = bevy::asset::ron::to_string(&inventory).unwrap();
+        let loaded = bevy::asset::ron::from_str::<Inventory>(&saved).unwrap();
+        assert_eq!(loaded.get("main", &UVec2::new(2, 0)), Some(&sword));
+        assert!(!loaded.is_blocked("main", &UVec2::new(0, 0)));
+        assert_eq!(loaded.required_tags("main", &UVec2::new(1, 0)), None);
+
+        // a new layout replaces the blocked indexes
+        inventory.set_blocked_indexes("main", [UVec2::new(1, 0)].into_iter().collect());
+        assert!(!inventory.is_blocked("main", &UVec2::new(0, 0)));
+        assert!(inventory.is_blocked("main", &UVec2::new(1, 0)));
+    }
+
+    #[test]
+    fn test_inventory_transfer() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "arrows".into(), display_name: "Arrows".into(), max_stack_size: 10, ..default() },
+            ItemType { type_name: "helmet".into(), display_name: "Helmet".into(), max_stack_size: 1, tags: vec![Tag("head".into())], ..default() },
+        ]);
+        let mut items = Items::default();
+        let arrows = items.add_items("arrows", 6, &item_types).unwrap()[0];
+        let more_arrows = items.add_items("arrows", 6, &item_types).unwrap()[0];
+        let helmet = items.add_item("helmet", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("equipment", UVec2::new(2, 1));
+        inventory.set_required_tags("equipment", UVec2::new(0, 0), Some(Tag("head".into()).into()));
+        inventory.set_blocked("equipment", UVec2::new(1, 0), true);
+        inventory.add("main", arrows, &items, &item_types, &validators).unwrap();
+        inventory.add("main", more_arrows, &items, &item_types, &validators).unwrap();
+        inventory.add("main", helmet, &items, &item_types, &validators).unwrap();
+
+        // arrows do not have the required tag, and nothing can be placed at the blocked index
+        assert_eq!(inventory.transfer("main", UVec2::new(0, 0), "equipment", UVec2::new(0, 0), &mut items, &item_types, &validators), Err(SiminvError::TagMismatch(arrows)));
+        assert_eq!(inventory.transfer("main", UVec2::new(0, 0), "equipment", UVec2::new(1, 0), &mut items, &item_types, &validators), Err(SiminvError::IndexOutOfBounds {
+            collection: "equipment".into(),
+            index: UVec2::new(1, 0),
+        }));
+        assert_eq!(inventory.quick_move("main", UVec2::new(2, 0), "equipment", &OnlyEmpty, &mut items, &item_types, &validators), Ok(vec![UVec2::new(0, 0)]));
+        assert_eq!(inventory.get("equipment", &UVec2::new(0, 0)), Some(&helmet));
+        assert_eq!(inventory.get("main", &UVec2::new(2, 0)), None);
+
+        // merges the stacks, the rest stays in place
+        assert_eq!(inventory.transfer("main", UVec2::new(0, 0), "main", UVec2::new(1, 0), &mut items, &item_types, &validators), Ok(()));
+        assert_eq!(inventory.get("main", &UVec2::new(0, 0)), Some(&arrows));
+        assert_eq!(items.get_item(arrows).unwrap().stack_size, 2);
+        assert_eq!(items.get_item(more_arrows).unwrap().stack_size, 10);
+
+        // moves onto empty index
+        assert_eq!(inventory.transfer("main", UVec2::new(0, 0), "other", UVec2::new(3, 0), &mut items, &item_types, &validators), Ok(()));
+        assert_eq!(inventory.get("other", &UVec2::new(3, 0)), Some(&arrows));
+        assert_eq!(inventory.quick_move("main", UVec2::new(0, 0), "other", &OnlyEmpty, &mut items, &item_types, &validators), Err(SiminvError::EmptyIndex {
+            collection: "main".into(),
+            index: UVec2::new(0, 0),
+        }));
+
+        // the equipment is full, so the helmet can only be replaced
+        let other_helmet = items.add_item("helmet", &item_types).unwrap();
+        let index = inventory.add("main", other_helmet, &items, &item_types, &validators).unwrap();
+        assert_eq!(inventory.quick_move("main", index, "equipment", &OnlyEmpty, &mut items, &item_types, &validators), Err(SiminvError::NoMatchingSlot(other_helmet)));
+        assert_eq!(inventory.quick_move("main", index, "equipment", &EmptyOrReplace, &mut items, &item_types, &validators), Ok(vec![UVec2::new(0, 0)]));
+        assert_eq!(inventory.get("equipment", &UVec2::new(0, 0)), Some(&other_helmet));
+        assert_eq!(inventory.get("main", &index), Some(&helmet));
+    }
+
+    #[test]
+    fn test_inventory_quick_move_merges_stacks() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "stones".into(),
+            display_name: "Stones".into(),
+            max_stack_size: 20,
+            ..default()
+        });
+        let mut items = Items::default();
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("stash", UVec2::new(3, 1));
+        for count in [17, 20, 19] {
+            let stack = items.add_items("stones", count, &item_types).unwrap()[0];
+            inventory.add("stash", stack, &items, &item_types, &validators).unwrap();
+        }
+
+        // tops up both partial stacks, the rest stays in the source
+        let stones = items.add_items("stones", 5, &item_types).unwrap()[0];
+        inventory.add("backpack", stones, &items, &item_types, &validators).unwrap();
+        assert_eq!(inventory.quick_move("backpack", UVec2::ZERO, "stash", &MergeFirst, &mut items, &item_types, &validators), Ok(vec![UVec2::new(0, 0), UVec2::new(2, 0)]));
+        assert_eq!(inventory.get("backpack", &UVec2::ZERO), Some(&stones));
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 1);
+
+        // full stacks can't take more and there are no empty indexes left
+        assert_eq!(inventory.quick_move("backpack", UVec2::ZERO, "stash", &MergeFirst, &mut items, &item_types, &validators), Err(SiminvError::NoMatchingSlot(stones)));
+
+        // the rest is spilled into an empty index
+        inventory.set_max_size("stash", UVec2::new(4, 1));
+        assert_eq!(inventory.quick_move("backpack", UVec2::ZERO, "stash", &MergeFirst, &mut items, &item_types, &validators), Ok(vec![UVec2::new(3, 0)]));
+        assert_eq!(inventory.get("stash", &UVec2::new(3, 0)), Some(&stones));
+        assert_eq!(inventory.get("backpack", &UVec2::ZERO), None);
+    }
+
+    #[test]
+    fn test_inventory_quick_move_sparse_grid() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "sword".into(),
+            display_name: "Sword".into(),
+            max_stack_size: 1,
+            ..default()
+        });
+        let mut items = Items::default();
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("backpack", UVec2::new(8, 4));
+        let first = items.add_item("sword", &item_types).unwrap();
+        inventory.add("backpack", first, &items, &item_types, &validators).unwrap();
+
+        // every row of a bounded collection is a candidate, not only the rows next to the used ones
+        let sword = items.add_item("sword", &item_types).unwrap();
+        inventory.add("main", sword, &items, &item_types, &validators).unwrap();
+        assert_eq!(inventory.quick_move("main", UVec2::ZERO, "backpack", &FillFromBottom, &mut items, &item_types, &validators), Ok(vec![UVec2::new(0, 3)]));
+        assert_eq!(inventory.get("backpack", &UVec2::new(0, 3)), Some(&sword));
+    }
+
+    #[test]
+    fn test_inventory_changes() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::with_validation(ItemValidation::Lenient);
+        item_types.register_item_type(ItemType {
+            type_name: "stones".into(),
+            display_name: "Stones".into(),
+            max_stack_size: 20,
+            ..default()
+        });
+        let mut items = Items::default();
+        let stones = items.add_items("stones", 5, &item_types).unwrap()[0];
+        let more_stones = items.add_items("stones", 5, &item_types).unwrap()[0];
+
+        let mut inventory = Inventory::default();
+        inventory.add("main", stones, &items, &item_types, &validators).unwrap();
+        inventory.add("main", more_stones, &items, &item_types, &validators).unwrap();
+        inventory.take_changes();
+
+        let change = |index: UVec2, old: Option<ItemId>, new: Option<ItemId>, reason: ChangeReason| InventoryChanged {
+            collection: "main".into(),
+            index,
+            old,
+            new,
+            reason,
+        };
+        inventory.transfer("main", UVec2::new(0, 0), "main", UVec2::new(1, 0), &mut items, &item_types, &validators).unwrap();
+        assert_eq!(inventory.take_changes(), vec![
+            change(UVec2::new(0, 0), Some(stones), None, ChangeReason::Transfer),
+            change(UVec2::new(1, 0), Some(more_stones), Some(more_stones), ChangeReason::Merge),
+        ]);
+
+        inventory.consume("main", UVec2::new(1, 0), 10, &mut items).unwrap();
+        assert_eq!(inventory.take_changes(), vec![change(UVec2::new(1, 0), Some(more_stones), None, ChangeReason::Remove)]);
+    }
+
+    #[test]
+    fn test_inventory_transaction() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "wood".into(), display_name: "Wood".into(), max_stack_size: 10, ..default() },
+            ItemType { type_name: "bow".into(), display_name: "Bow".into(), max_stack_size: 1, shape: ItemShape::rect(1, 2), ..default() },
+        ]);
+        let mut items = Items::default();
+        let wood = items.add_items("wood", 5, &item_types).unwrap()[0];
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("backpack", UVec2::new(2, 1));
+        inventory.add("backpack", wood, &items, &item_types, &validators).unwrap();
+        inventory.take_modified();
+
+        let craft_bow = |inventory: &mut Inventory, items: &mut Items| {
+            inventory.consume("backpack", UVec2::ZERO, 3, items)?;
+            let bow = items.add_item("bow", &item_types)?;
+            inventory.add("backpack", bow, items, &item_types, &validators)
+        };
+
+        // the bow does not fit into one row, so the wood is not consumed
+        assert_eq!(inventory.transaction(&mut items, craft_bow), Err(SiminvError::CollectionFull("backpack".into())));
+        assert_eq!(items.get_item(wood).unwrap().stack_size, 5);
+        assert_eq!(items.find_orphans(&[&inventory]), vec![]);
+        assert!(inventory.take_modified().is_empty());
+
+        inventory.set_max_size("backpack", UVec2::new(2, 2));
+        assert_eq!(inventory.transaction(&mut items, craft_bow), Ok(UVec2::new(1, 0)));
+        assert_eq!(items.get_item(wood).unwrap().stack_size, 2);
+        assert_eq!(inventory.take_modified(), vec![("backpack".into(), UVec2::new(0, 0)), ("backpack".into(), UVec2::new(1, 0))]);
+
+        // cells of multi-cell items and new collections are restored as well
+        let bow = *inventory.get("backpack", &UVec2::new(1, 0)).unwrap();
+        let store_bow = |inventory: &mut Inventory, items: &mut Items| {
+            inventory.remove("backpack", UVec2::new(1, 0))?;
+            let planks = items.add_items("wood", 4, &item_types)?[0];
+            inventory.add("chest", planks, items, &item_types, &validators)?;
+            inventory.consume("backpack", UVec2::ZERO, 3, items)
+        };
+        assert_eq!(inventory.transaction(&mut items, store_bow), Err(SiminvError::NotEnoughItems { item: wood, available: 2 }));
+        assert_eq!(inventory.get_occupant("backpack", &UVec2::new(1, 1)), Some((UVec2::new(1, 0), bow)));
+        assert!(inventory.collections().all(|collection| collection != "chest"));
+        assert_eq!(items.find_orphans(&[&inventory]), vec![]);
+        assert!(inventory.take_modified().is_empty());
+    }
+
+    #[test]
+    fn test_inventory_collection_filter() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "arrows".into(), display_name: "Arrows".into(), max_stack_size: 10, tags: vec![Tag("ammo/arrow".into())], ..default() },
+            ItemType { type_name: "helmet".into(), display_name: "Helmet".into(), max_stack_size: 1, tags: vec![Tag("head".into())], ..default() },
+        ]);
+        let mut items = Items::default();
+        let arrows = items.add_items("arrows", 5, &item_types).unwrap()[0];
+        let helmet = items.add_item("helmet", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        inventory.set_filter("pouch", Some(Tag("ammo".into()).into()));
+        inventory.add("main", arrows, &items, &item_types, &validators).unwrap();
+        inventory.add("main", helmet, &items, &item_types, &validators).unwrap();
+
+        let rejected = SiminvError::RejectedByCollection { collection: "pouch".into(), item: helmet };
+        assert_eq!(inventory.add("pouch", helmet, &items, &item_types, &validators), Err(rejected.clone()));
+        assert_eq!(inventory.set("pouch", UVec2::ZERO, helmet, &items, &item_types, &validators), Err(rejected.clone()));
+        assert_eq!(inventory.transfer("main", UVec2::X, "pouch", UVec2::ZERO, &mut items, &item_types, &validators), Err(rejected));
+        assert_eq!(inventory.quick_move("main", UVec2::X, "pouch", &OnlyEmpty, &mut items, &item_types, &validators), Err(SiminvError::NoMatchingSlot(helmet)));
+        assert_eq!(inventory.quick_move("main", UVec2::ZERO, "pouch", &OnlyEmpty, &mut items, &item_types, &validators), Ok(vec![UVec2::ZERO]));
+
+        // the filter is saved with the collection
+        let inventory: Inventory = bevy::asset::ron::from_str(&bevy::asset::ron::to_string(&inventory).unwrap()).unwrap();
+        assert_eq!(inventory.filter("pouch"), Some(&TagRule::from(Tag("ammo".into()))));
+        assert!(!inventory.accepts("pouch", &[Tag("head".into())]));
+    }
+
+    #[test]
+    fn test_inventory_max_weight() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "stones".into(), display_name: "Stones".into(), max_stack_size: 10, weight: 0.5, ..default() },
+            ItemType { type_name: "armor".into(), display_name: "Armor".into(), max_stack_size: 1, weight: 8., ..default() },
+        ]);
+        let mut items = Items::default();
+        let stones = items.add_items("stones", 6, &item_types).unwrap()[0];
+        let more_stones = items.add_items("stones", 6, &item_types).unwrap()[0];
+        let armor = items.add_item("armor", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        inventory.set_max_weight("pouch", Some(4.));
+        inventory.add("main", armor, &items, &item_types, &validators).unwrap();
+        inventory.add("main", stones, &items, &item_types, &validators).unwrap();
+        inventory.add("pouch", more_stones, &items, &item_types, &validators).unwrap();
+        assert_eq!(inventory.weight("pouch", &items, &item_types), 3.);
+        assert_eq!(inventory.max_weight("pouch"), Some(4.));
+
+        let too_heavy = SiminvError::TooHeavy { collection: "pouch".into(), item: armor };
+        assert_eq!(inventory.add("pouch", armor, &items, &item_types, &validators), Err(too_heavy.clone()));
+        assert_eq!(inventory.transfer("main", UVec2::ZERO, "pouch", UVec2::X, &mut items, &item_types, &validators), Err(too_heavy));
+        // merging moves only 4 stones, which still weigh too much
+        assert!(inventory.transfer("main", UVec2::X, "pouch", UVec2::ZERO, &mut items, &item_types, &validators).is_err());
+
+        // taking weight away is fine
+        inventory.set_max_weight("pouch", Some(2.));
+        assert_eq!(inventory.transfer("pouch", UVec2::ZERO, "main", UVec2::X, &mut items, &item_types, &validators), Ok(()));
+        assert_eq!(inventory.weight("pouch", &items, &item_types), 1.);
+        assert_eq!(inventory.weight("main", &items, &item_types), 13.);
+    }
+
+    #[test]
+    fn test_inventory_queries() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "arrows".into(), display_name: "Arrows".into(), max_stack_size: 10, tags: vec![Tag("ammo/arrow".into())], ..default() },
+            ItemType { type_name: "bow".into(), display_name: "Bow".into(), max_stack_size: 1, tags: vec![Tag("weapon".into())], shape: ItemShape::rect(1, 2), ..default() },
+        ]);
+        let mut items = Items::default();
+        let arrows = items.add_items("arrows", 25, &item_types).unwrap();
+        let bow = items.add_item("bow", &item_types).unwrap();
+
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("backpack", UVec2::new(3, 2));
+        inventory.set_blocked("backpack", UVec2::new(2, 1), true);
+        inventory.add("backpack", bow, &items, &item_types, &validators).unwrap();
+        inventory.add("backpack", arrows[0], &items, &item_types, &validators).unwrap();
+        inventory.add("quiver", arrows[1], &items, &item_types, &validators).unwrap();
+        inventory.add("quiver", arrows[2], &items, &item_types, &validators).unwrap();
+
+        assert_eq!(inventory.count("arrows", None, &items), 25);
+        assert_eq!(inventory.count("arrows", Some("quiver"), &items), 15);
+        assert!(inventory.contains("arrows", 20, None, &items));
+        assert!(!inventory.contains("bow", 1, Some("quiver"), &items));
+
+        assert_eq!(inventory.find_by_type("arrows", None, &items), vec![
+            handle("backpack", UVec2::new(1, 0)),
+            handle("quiver", UVec2::new(0, 0)),
+            handle("quiver", UVec2::new(1, 0)),
+        ]);
+        assert_eq!(inventory.find_by_tag(&Tag("ammo".into()), Some("quiver"), &items, &item_types).len(), 2);
+        assert_eq!(inventory.find_by_tag(&Tag("weapon".into()), None, &items, &item_types), vec![handle("backpack", UVec2::ZERO)]);
+
+        // the bow covers 2 cells, one is blocked
+        assert_eq!(inventory.first_free_index("backpack"), Some(UVec2::new(2, 0)));
+        assert_eq!(inventory.free_slot_count("backpack"), 2);
+        assert_eq!(inventory.first_free_index("quiver"), Some(UVec2::new(2, 0)));
+
+        let mut listed = inventory.iter(Some("backpack"), &items, &item_types)
+            .map(|(collection, index, item, meta)| (collection, index, item, meta.stack_size))
+            .collect::<Vec<_>>();
+        listed.sort_by_key(|(_, index, _, _)| index.x);
+        assert_eq!(listed, vec![("backpack", UVec2::ZERO, bow, 1), ("backpack", UVec2::X, arrows[0], 10)]);
+        assert_eq!(inventory.iter(None, &items, &item_types).count(), 4);
+    }
+
+    #[test]
+    fn test_inventory_sort_collection() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "stones".into(), display_name: "Stones".into(), max_stack_size: 10, ..default() },
+            ItemType { type_name: "bow".into(), display_name: "Bow".into(), max_stack_size: 1, tags: vec![Tag("weapon".into())], ..default() },
+            ItemType { type_name: "arrows".into(), display_name: "Arrows".into(), max_stack_size: 10, tags: vec![Tag("ammo".into())], ..default() },
+        ]);
+        let mut items = Items::default();
+        let mut inventory = Inventory::default();
+        inventory.set_max_size("stash", UVec2::new(3, 2));
+        inventory.set_blocked("stash", UVec2::new(1, 0), true);
+        inventory.set_required_tags("stash", UVec2::new(2, 0), Some(Tag("weapon".into()).into()));
+        let stones = items.add_items("stones", 4, &item_types).unwrap()[0];
+        let more_stones = items.add_items("stones", 8, &item_types).unwrap()[0];
+        let arrows = items.add_items("arrows", 5, &item_types).unwrap()[0];
+        let bow = items.add_item("bow", &item_types).unwrap();
+        inventory.set("stash", UVec2::new(2, 1), stones, &items, &item_types, &validators).unwrap();
+        inventory.set("stash", UVec2::new(1, 1), more_stones, &items, &item_types, &validators).unwrap();
+        inventory.set("stash", UVec2::new(0, 1), arrows, &items, &item_types, &validators).unwrap();
+        inventory.set("stash", UVec2::new(0, 0), bow, &items, &item_types, &validators).unwrap();
+        inventory.take_changes();
+
+        inventory.sort_collection("stash", SortKey::TypeName, &mut items, &item_types, &validators).unwrap();
+        // stones are merged into the first stack, the blocked index is skipped, and only the bow fits into the weapon slot
+        assert_eq!(inventory.get("stash", &UVec2::new(0, 0)), Some(&arrows));
+        assert_eq!(inventory.get("stash", &UVec2::new(2, 0)), Some(&bow));
+        assert_eq!(inventory.get("stash", &UVec2::new(0, 1)), Some(&more_stones));
+        assert_eq!(inventory.get("stash", &UVec2::new(1, 1)), Some(&stones));
+        assert_eq!(items.get_item(more_stones).unwrap().stack_size, 10);
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 2);
+        assert!(inventory.take_changes().iter().any(|change| change.reason == ChangeReason::Sort));
+
+        inventory.sort_collection("stash", SortKey::Custom(|a, b| a.stack_size.cmp(&b.stack_size)), &mut items, &item_types, &validators).unwrap();
+        // smallest stacks first
+        assert_eq!(inventory.get("stash", &UVec2::new(0, 0)), Some(&bow));
+        assert_eq!(inventory.get("stash", &UVec2::new(0, 1)), Some(&stones));
+        assert_eq!(inventory.get("stash", &UVec2::new(1, 1)), Some(&arrows));
+        assert_eq!(inventory.get("stash", &UVec2::new(2, 1)), Some(&more_stones));
+    }
+
+    #[test]
+    fn test_inventory_stack_all() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "stones".into(), display_name: "Stones".into(), max_stack_size: 20, ..default() },
+            ItemType { type_name: "arrows".into(), display_name: "Arrows".into(), max_stack_size: 10, ..default() },
+        ]);
+        let mut items = Items::default();
+        let mut inventory = Inventory::default();
+        let mut add = |collection: &str, type_name: &str, count: u64, items: &mut Items| {
+            let item = items.add_items(type_name, count, &item_types).unwrap()[0];
+            inventory.add(collection, item, items, &item_types, &validators).unwrap();
+            item
+        };
+        let stones = [8, 3, 3, 9].map(|count| add("stash", "stones", count, &mut items));
+        let arrows = add("stash", "arrows", 4, &mut items);
+        let loot_stones = add("backpack", "stones", 5, &mut items);
+        let loot_arrows = add("backpack", "arrows", 9, &mut items);
+        let bow_arrows = add("backpack", "arrows", 1, &mut items);
+
+        inventory.stack_all("stash", &mut items, &item_types, &validators).unwrap();
+        assert_eq!(items.get_item(stones[0]).unwrap().stack_size, 20);
+        assert_eq!(items.get_item(stones[3]).unwrap().stack_size, 3);
+        assert_eq!(items.get_item(stones[1]), None);
+        assert_eq!(inventory.find_by_type("stones", Some("stash"), &items), vec![handle("stash", UVec2::new(0, 0)), handle("stash", UVec2::new(3, 0))]);
+
+        // the rest of the arrows stays in the backpack, once the stack in the stash is full
+        inventory.quick_stack("backpack", "stash", &mut items, &item_types, &validators).unwrap();
+        assert_eq!(items.get_item(stones[3]).unwrap().stack_size, 8);
+        assert_eq!(items.get_item(loot_stones), None);
+        assert_eq!(items.get_item(arrows).unwrap().stack_size, 10);
+        assert_eq!(items.get_item(loot_arrows).unwrap().stack_size, 3);
+        assert_eq!(items.get_item(bow_arrows).unwrap().stack_size, 1);
+        assert_eq!(inventory.count("arrows", Some("backpack"), &items), 4);
+    }
+
+    #[test]
+    fn test_inventory_split() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_types([
+            ItemType { type_name: "stones".into(), display_name: "Stones".into(), max_stack_size: 20, ..default() },
+            ItemType { type_name: "bow".into(), display_name: "Bow".into(), max_stack_size: 1, ..default() },
+        ]);
+        let mut items = Items::default();
+        let stones = items.add_items("stones", 17, &item_types).unwrap()[0];
+        let more_stones = items.add_items("stones", 15, &item_types).unwrap()[0];
+        let bow = items.add_item("bow", &item_types).unwrap();
+        let mut inventory = Inventory::default();
+        for item in [stones, more_stones, bow] {
+            inventory.add("main", item, &items, &item_types, &validators).unwrap();
+        }
+
+        assert_eq!(items.split(stones, 17), Err(SiminvError::InvalidSplitAmount { item: stones, amount: 17 }));
+        assert_eq!(inventory.split("main", UVec2::ZERO, "stash", UVec2::ZERO, 8, &mut items, &item_types, &validators), Ok(()));
+        let split = *inventory.get("stash", &UVec2::ZERO).unwrap();
+        assert_eq!(items.get_item(split).unwrap().stack_size, 8);
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 9);
+
+        // only 5 fit into the other stack, the rest returns
+        assert_eq!(inventory.split("main", UVec2::ZERO, "main", UVec2::X, 7, &mut items, &item_types, &validators), Ok(()));
+        assert_eq!(items.get_item(more_stones).unwrap().stack_size, 20);
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 4);
+
+        // split stacks are not swapped, nothing changes
+        let count = items.find_orphans(&[]).len();
+        assert_eq!(inventory.split("main", UVec2::ZERO, "main", UVec2::new(2, 0), 2, &mut items, &item_types, &validators), Err(SiminvError::IndexOccupied {
+            collection: "main".into(),
+            index: UVec2::new(2, 0),
+        }));
+        assert_eq!(items.get_item(stones).unwrap().stack_size, 4);
+        assert_eq!(items.find_orphans(&[]).len(), count);
+    }
+
+    #[test]
+    fn test_inventory_stack_overflow() {
+        let validators = MoveValidators::default();
+        let mut item_types = ItemTypes::default();
+        let stones = ItemType { type_name: "stones".into(), display_name: "Stones".into(), max_stack_size: 20, ..default() };
+        item_types.register_item_type(stones.clone());
+        let mut items = Items::default();
+        let big = items.add_items("stones", 15, &item_types).unwrap()[0];
+        let small = items.add_items("stones", 5, &item_types).unwrap()[0];
+        let mut inventory = Inventory::default();
+        inventory.add("main", small, &items, &item_types, &validators).unwrap();
+
+        // item types reloaded with a lower max stack size
+        item_types.register_or_update_item_type(ItemType { max_stack_size: 10, ..stones });
+        let overflow = SiminvError::StackOverflow { item: big, max_stack_size: 10 };
+        assert_eq!(inventory.add("main", big, &items, &item_types, &validators), Err(overflow.clone()));
+        assert_eq!(inventory.set("main", UVec2::X, big, &items, &item_types, &validators), Err(overflow.clone()));
+        assert_eq!(items.merge_or_swap(big, small, &item_types), Err(overflow));
+        assert_eq!(items.get_item(small).unwrap().stack_size, 5);
+    }
+}
@@ -1,7 +1,9 @@
-use std::marker::PhantomData;
-use bevy::{platform::collections::HashMap, prelude::*};
+use std::{collections::BTreeMap, marker::PhantomData};
+use bevy::{platform::collections::{HashMap, HashSet}, prelude::*};
 use serde::{Deserialize, Serialize};
 
+use crate::error::SiminvError;
+
 #[derive(Debug, PartialEq, Hash, Clone, Copy, Eq, Serialize, Deserialize)]
 pub struct ItemTypeId(u64);
 
@@ -15,7 +17,65 @@ impl From<u64> for ItemTypeId {
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct Tag(pub String);
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Tags required from items placed in a slot.
+///
+/// In RON: `AnyOf([Tag("ring"), Tag("amulet")])`,
+/// or `All([AllOf([Tag("weapon"), Tag("one-handed")]), NoneOf([Tag("quest")])])`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub enum TagRule {
+    /// Item has at least one of the tags.
+    AnyOf(Vec<Tag>),
+    /// Item has every one of the tags.
+    AllOf(Vec<Tag>),
+    /// Item has none of the tags.
+    NoneOf(Vec<Tag>),
+    /// Item passes every one of the rules.
+    All(Vec<TagRule>),
+}
+
+impl TagRule {
+    pub fn matches(&self, tags: &[Tag]) -> bool {
+        match self {
+            TagRule::AnyOf(required) => required.iter().any(|tag| tags.contains(tag)),
+            TagRule::AllOf(required) => required.iter().all(|tag| tags.contains(tag)),
+            TagRule::NoneOf(forbidden) => !forbidden.iter().any(|tag| tags.contains(tag)),
+            TagRule::All(rules) => rules.iter().all(|rule| rule.matches(tags)),
+        }
+    }
+}
+
+impl From<Tag> for TagRule {
+    fn from(tag: Tag) -> Self {
+        TagRule::AllOf(vec![tag])
+    }
+}
+
+/// Short description of the rule, e.g. `ring | amulet` or `weapon & !quest`.
+impl std::fmt::Display for TagRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let joined = |tags: &[Tag], prefix: &str, separator: &str| tags.iter()
+            .map(|Tag(tag)| format!("{prefix}{tag}"))
+            .collect::<Vec<_>>()
+            .join(separator);
+        match self {
+            TagRule::AnyOf(tags) => write!(f, "{}", joined(tags, "", " | ")),
+            TagRule::AllOf(tags) => write!(f, "{}", joined(tags, "", " & ")),
+            TagRule::NoneOf(tags) => write!(f, "{}", joined(tags, "!", " & ")),
+            TagRule::All(rules) => write!(f, "{}", rules.iter()
+                .map(|rule| match rule {
+                    // keeps the precedence readable
+                    TagRule::AnyOf(tags) if tags.len() > 1 => format!("({rule})"),
+                    _ => rule.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" & ")),
+        }
+    }
+}
+
+/// Unset fields default to a weightless single cell item without tags, e.g.
+/// `ItemType { type_name: "stones".into(), display_name: "Stones".into(), max_stack_size: 20, ..default() }`.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct ItemType {
     /// Unique type_name that can be used to identify this item type.
     pub type_name: String,
@@ -25,6 +85,77 @@ pub struct ItemType {
     pub max_stack_size: u64,
     /// Tags
     pub tags: Vec<Tag>,
+    /// Cells occupied by the item in a grid collection.
+    #[serde(default)]
+    pub shape: ItemShape,
+    /// Weight of a single item, stacks weigh `weight * stack_size`.
+    /// Negative and NaN weights are not allowed, they would offset the weight of other items.
+    #[serde(default)]
+    pub weight: f32,
+}
+
+impl ItemType {
+    pub fn has_valid_weight(&self) -> bool {
+        self.weight.is_finite() && self.weight >= 0.
+    }
+}
+
+/// Footprint of an item inside of a collection.
+///
+/// Cells are relative to the top-left anchor of the item. The anchor cell (0, 0)
+/// is always occupied, even if it is not listed explicitly.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
+pub enum ItemShape {
+    /// Item occupies a single cell.
+    #[default]
+    Single,
+    /// Item occupies a rectangle of cells.
+    Rect { width: u32, height: u32 },
+    /// Item occupies an arbitrary set of cells, e.g. a polyomino.
+    Cells(Vec<UVec2>),
+}
+
+impl ItemShape {
+    pub fn rect(width: u32, height: u32) -> Self {
+        ItemShape::Rect { width, height }
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.size() == UVec2::ONE
+    }
+
+    /// Cells occupied by the item, relative to its anchor.
+    pub fn cells(&self) -> Vec<UVec2> {
+        match *self {
+            ItemShape::Single => vec![UVec2::ZERO],
+            ItemShape::Rect { width, height } => (0..height.max(1))
+                .flat_map(|y| (0..width.max(1)).map(move |x| UVec2::new(x, y)))
+                .collect(),
+            ItemShape::Cells(ref cells) => {
+                let mut result = cells.clone();
+                if !result.contains(&UVec2::ZERO) {
+                    result.push(UVec2::ZERO);
+                }
+                result
+            }
+        }
+    }
+
+    /// Cells occupied by the item placed at `anchor`.
+    /// Returns None if any of the cells does not fit into u32 coordinates.
+    pub fn cells_at(&self, anchor: UVec2) -> Option<Vec<UVec2>> {
+        self.cells()
+            .into_iter()
+            .map(|cell| Some(UVec2::new(anchor.x.checked_add(cell.x)?, anchor.y.checked_add(cell.y)?)))
+            .collect()
+    }
+
+    /// Size of the bounding box of the shape.
+    pub fn size(&self) -> UVec2 {
+        self.cells()
+            .into_iter()
+            .fold(UVec2::ONE, |size, cell| size.max(cell + UVec2::ONE))
+    }
 }
 
 #[derive(Debug, PartialEq, Hash, Clone, Copy, Eq, Deserialize, Serialize)]
@@ -36,11 +167,138 @@ impl From<u64> for ItemId {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Value of a single per-instance item property.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ItemProperty {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<ItemProperty>),
+}
+
+impl From<bool> for ItemProperty {
+    fn from(value: bool) -> Self {
+        ItemProperty::Bool(value)
+    }
+}
+
+impl From<i64> for ItemProperty {
+    fn from(value: i64) -> Self {
+        ItemProperty::Int(value)
+    }
+}
+
+impl From<f64> for ItemProperty {
+    fn from(value: f64) -> Self {
+        ItemProperty::Float(value)
+    }
+}
+
+impl From<&str> for ItemProperty {
+    fn from(value: &str) -> Self {
+        ItemProperty::Text(value.to_string())
+    }
+}
+
+impl From<String> for ItemProperty {
+    fn from(value: String) -> Self {
+        ItemProperty::Text(value)
+    }
+}
+
+impl From<Vec<ItemProperty>> for ItemProperty {
+    fn from(value: Vec<ItemProperty>) -> Self {
+        ItemProperty::List(value)
+    }
+}
+
+/// Per-instance properties of an item, e.g. durability, charges or affixes.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemProperties(BTreeMap<String, ItemProperty>);
+
+impl ItemProperties {
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<ItemProperty>) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ItemProperty> {
+        self.0.get(key)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            ItemProperty::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            ItemProperty::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            ItemProperty::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_text(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            ItemProperty::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_list(&self, key: &str) -> Option<&[ItemProperty]> {
+        match self.get(key)? {
+            ItemProperty::List(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets the property and returns its previous value.
+    pub fn set(&mut self, key: impl Into<String>, value: impl Into<ItemProperty>) -> Option<ItemProperty> {
+        self.0.insert(key.into(), value.into())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<ItemProperty> {
+        self.0.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ItemProperty)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value))
+    }
+}
+
+/// Decides if two stacks of the same type, but with different properties, can be merged.
+/// By default, only stacks with equal properties are merged.
+#[derive(Clone, Copy)]
+pub struct MergeCompatibility(pub fn(&ItemProperties, &ItemProperties) -> bool);
+
+impl Default for MergeCompatibility {
+    fn default() -> Self {
+        MergeCompatibility(|a, b| a == b)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Item {
     /// Unique type_name that can be used to identify this item type.
     pub type_name: String,
     pub stack_size: u64,
+    /// Per-instance properties.
+    #[serde(default)]
+    pub properties: ItemProperties,
 }
 
 #[derive(Debug)]
@@ -49,11 +307,16 @@ pub struct ItemMeta<'a> {
     pub display_name: &'a str,
     pub stack_size: u64,
     pub max_stack_size: u64,
-    pub tags: &'a [Tag]
+    /// Tags of the item type together with their ancestors.
+    pub tags: &'a [Tag],
+    pub shape: &'a ItemShape,
+    pub properties: &'a ItemProperties,
+    /// Weight of a single item of the stack.
+    pub weight: f32,
 }
 
 /// Generates item ids used in runtime.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct IdFactory<I> {
     current_id: u64,
     id_type: PhantomData<I>,
@@ -76,19 +339,121 @@ impl<I: From<u64>> IdFactory<I> {
     }
 }
 
-#[derive(Resource, Default, Serialize, Deserialize)]
-pub struct Items {
+/// Parent/child relations of tags. Items with a child tag, e.g. `weapon/sword/longsword`,
+/// match rules requiring any of its ancestors, e.g. `weapon/sword` or `weapon`.
+///
+/// Tags with `/` in their names are children of the tag before the last `/`,
+/// other parents are added with `ItemTypes::add_tag_parent`.
+#[derive(Debug, Default, Clone)]
+pub struct TagRegistry {
+    parents: HashMap<String, Vec<String>>,
+}
+
+impl TagRegistry {
+    pub fn add_parent(&mut self, child: &Tag, parent: &Tag) {
+        let parents = self.parents.entry(child.0.clone()).or_default();
+        if !parents.contains(&parent.0) {
+            parents.push(parent.0.clone());
+        }
+    }
+
+    /// Direct parents of the tag, including the one implied by its path.
+    pub fn parents<'a>(&'a self, tag: &'a Tag) -> impl Iterator<Item = &'a str> {
+        tag.0.rsplit_once('/')
+            .map(|(parent, _)| parent)
+            .into_iter()
+            .chain(self.parents.get(&tag.0).into_iter().flatten().map(String::as_str))
+    }
+
+    /// Tags together with all their ancestors, without duplicates.
+    pub fn expand(&self, tags: &[Tag]) -> Vec<Tag> {
+        let mut expanded = tags.to_vec();
+        // visits the added parents as well, every tag only once, so cyclic relations terminate
+        let mut i = 0;
+        while let Some(tag) = expanded.get(i) {
+            let parents = self.parents(tag)
+                .map(|parent| Tag(parent.to_string()))
+                .collect::<Vec<_>>();
+            for parent in parents {
+                if !expanded.contains(&parent) {
+                    expanded.push(parent);
+                }
+            }
+            i += 1;
+        }
+        expanded
+    }
+}
+
+/// Decides how `Items` creation methods treat type names that are not registered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ItemValidation {
+    /// Items of unknown types are rejected.
+    #[default]
+    Strict,
+    /// Items of unknown types are created as a single stack.
+    Lenient,
+}
+
+/// Registry of item types.
+///
+/// Item types are static game data, so unlike `Items`, the registry is not meant to be persisted.
+/// Register the types on every startup, e.g. with `ItemTypeDatabase` assets.
+#[derive(Resource, Default)]
+pub struct ItemTypes {
     item_type_ids: IdFactory<ItemTypeId>,
-    item_ids: IdFactory<ItemId>,
     item_types: HashMap<ItemTypeId, ItemType>,
     item_types_by_type_name: HashMap<String, ItemTypeId>,
-    items: HashMap<ItemId, Item>,
+    validation: ItemValidation,
+    merge_compatibility: MergeCompatibility,
+    tag_registry: TagRegistry,
+    // tags of every item type together with their ancestors, so matching does not walk the hierarchy
+    expanded_tags: HashMap<ItemTypeId, Vec<Tag>>,
 }
 
-impl Items {
+impl ItemTypes {
+    pub fn with_validation(validation: ItemValidation) -> Self {
+        ItemTypes {
+            validation,
+            ..default()
+        }
+    }
+
+    pub fn validation(&self) -> ItemValidation {
+        self.validation
+    }
+
+    pub fn set_validation(&mut self, validation: ItemValidation) {
+        self.validation = validation;
+    }
+
+    pub fn merge_compatibility(&self) -> MergeCompatibility {
+        self.merge_compatibility
+    }
+
+    /// Sets the rule used by `Items::merge_or_swap` for stacks with different properties.
+    pub fn set_merge_compatibility(&mut self, compatible: fn(&ItemProperties, &ItemProperties) -> bool) {
+        self.merge_compatibility = MergeCompatibility(compatible);
+    }
+
+    pub fn tag_registry(&self) -> &TagRegistry {
+        &self.tag_registry
+    }
+
+    /// Items tagged with `child` match rules requiring the `parent` as well.
+    pub fn add_tag_parent(&mut self, child: &Tag, parent: &Tag) {
+        self.tag_registry.add_parent(child, parent);
+        for (id, item_type) in &self.item_types {
+            self.expanded_tags.insert(*id, self.tag_registry.expand(&item_type.tags));
+        }
+    }
+
+    /// Item types with invalid weights are registered as weightless.
     pub fn register_item_type(&mut self, item_type: ItemType) -> ItemTypeId {
+        let item_type = with_valid_weight(item_type);
         let id = self.item_type_ids.next_id();
         self.item_types_by_type_name.insert(item_type.type_name.clone(), id);
+        self.expanded_tags.insert(id, self.tag_registry.expand(&item_type.tags));
         self.item_types.insert(id, item_type);
         id
     }
@@ -99,23 +464,36 @@ impl Items {
         })
     }
 
-    pub fn add_item(&mut self, type_name: &str) -> ItemId {
-        self.add_items(type_name, 1)
+    /// Registers a new item type, or replaces the existing item type with the same type_name.
+    /// Replaced item types keep their ids.
+    pub fn register_or_update_item_type(&mut self, item_type: ItemType) -> ItemTypeId {
+        match self.item_types_by_type_name.get(&item_type.type_name) {
+            Some(id) => {
+                let id = *id;
+                let item_type = with_valid_weight(item_type);
+                self.expanded_tags.insert(id, self.tag_registry.expand(&item_type.tags));
+                self.item_types.insert(id, item_type);
+                id
+            },
+            None => self.register_item_type(item_type),
+        }
     }
 
-    pub fn add_items(&mut self, type_name: &str, count: u64) -> ItemId {
-        let item = Item {
-            type_name: type_name.to_string(),
-            stack_size: count,
-        };
+    pub fn register_or_update_item_types(&mut self, item_types: impl IntoIterator<Item = ItemType>) {
+        item_types.into_iter().for_each(|t| {
+            self.register_or_update_item_type(t);
+        })
+    }
 
-        let id = self.item_ids.next_id();
-        self.items.insert(id, item);
-        id
+    /// Existing items of the removed type are left as they are, see `Items::unknown_item_types`.
+    pub fn unregister_item_type(&mut self, type_name: &str) -> Option<ItemType> {
+        let id = self.item_types_by_type_name.remove(type_name)?;
+        self.expanded_tags.remove(&id);
+        self.item_types.remove(&id)
     }
 
-    pub fn get_item(&self, id: ItemId) -> Option<&Item> {
-        self.items.get(&id)
+    pub fn is_empty(&self) -> bool {
+        self.item_types.is_empty()
     }
 
     pub fn get_item_type_with_type_name(&self, type_name: &str) -> Option<&ItemType> {
@@ -123,57 +501,337 @@ impl Items {
             .and_then(|type_id| self.item_types.get(type_id))
     }
 
-    pub fn get_item_meta(&self, id: ItemId) -> Option<ItemMeta<'_>> {
+    /// Tags of the item type together with all their ancestors.
+    pub fn get_tags_with_type_name(&self, type_name: &str) -> Option<&[Tag]> {
+        self.item_types_by_type_name.get(type_name)
+            .and_then(|type_id| self.expanded_tags.get(type_id))
+            .map(Vec::as_slice)
+    }
+}
+
+fn with_valid_weight(mut item_type: ItemType) -> ItemType {
+    if !item_type.has_valid_weight() {
+        warn!("item type {} has invalid weight {}, it is treated as weightless", item_type.type_name, item_type.weight);
+        item_type.weight = 0.;
+    }
+    item_type
+}
+
+/// Anything that references items, e.g. `Inventory` or slots that are not backed by an inventory.
+/// Used to find items that are not referenced anymore.
+pub trait ItemHolder {
+    fn held_items(&self) -> Vec<ItemId>;
+}
+
+impl ItemHolder for Vec<ItemId> {
+    fn held_items(&self) -> Vec<ItemId> {
+        self.clone()
+    }
+}
+
+// state of `Items` to return to if a transaction fails
+pub(crate) struct ItemsCheckpoint {
+    journal: HashMap<ItemId, Option<Item>>,
+    next_id: u64,
+}
+
+/// Item instances. Persist this resource to save the game.
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
+pub struct Items {
+    item_ids: IdFactory<ItemId>,
+    items: HashMap<ItemId, Item>,
+    // state of the changed items before the first change, used by `History`
+    #[serde(skip)]
+    journal: HashMap<ItemId, Option<Item>>,
+}
+
+impl Items {
+    pub fn add_item(&mut self, type_name: &str, item_types: &ItemTypes) -> Result<ItemId, SiminvError> {
+        let ids = self.add_items(type_name, 1, item_types)?;
+        Ok(ids[0])
+    }
+
+    /// Creates `count` items of given type, split into stacks of max stack size.
+    /// Returns ids of all created stacks.
+    pub fn add_items(&mut self, type_name: &str, count: u64, item_types: &ItemTypes) -> Result<Vec<ItemId>, SiminvError> {
+        self.add_items_with_properties(type_name, count, ItemProperties::default(), item_types)
+    }
+
+    /// Same as `add_items`, but every created stack has given properties.
+    pub fn add_items_with_properties(
+        &mut self,
+        type_name: &str,
+        count: u64,
+        properties: ItemProperties,
+        item_types: &ItemTypes,
+    ) -> Result<Vec<ItemId>, SiminvError> {
+        let max_stack_size = match (item_types.get_item_type_with_type_name(type_name), item_types.validation()) {
+            (Some(item_type), _) => item_type.max_stack_size.max(1),
+            (None, ItemValidation::Lenient) => count.max(1),
+            (None, ItemValidation::Strict) => return Err(SiminvError::UnknownTypeName(type_name.to_string())),
+        };
+
+        let mut ids = Vec::new();
+        let mut remaining = count;
+        while remaining > 0 {
+            let stack_size = remaining.min(max_stack_size);
+            remaining -= stack_size;
+
+            let item = Item {
+                type_name: type_name.to_string(),
+                stack_size,
+                properties: properties.clone(),
+            };
+
+            let id = self.item_ids.next_id();
+            self.touch(id);
+            self.items.insert(id, item);
+            ids.push(id);
+        }
+
+        Ok(ids)
+    }
+
+    pub fn get_item(&self, id: ItemId) -> Option<&Item> {
+        self.items.get(&id)
+    }
+
+    /// Removes the item. Make sure it is no longer referenced, e.g. by the `Inventory`.
+    pub fn remove_item(&mut self, id: ItemId) -> Result<Item, SiminvError> {
+        self.touch(id);
+        self.items.remove(&id).ok_or(SiminvError::UnknownItemId(id))
+    }
+
+    /// Removes `amount` items from the stack and returns the number of remaining items.
+    /// The item is removed when its stack is empty.
+    pub fn consume(&mut self, id: ItemId, amount: u64) -> Result<u64, SiminvError> {
+        self.touch(id);
+        let item = self.items.get_mut(&id).ok_or(SiminvError::UnknownItemId(id))?;
+        if item.stack_size < amount {
+            return Err(SiminvError::NotEnoughItems { item: id, available: item.stack_size })
+        }
+
+        item.stack_size -= amount;
+        let remaining = item.stack_size;
+        if remaining == 0 {
+            self.items.remove(&id);
+        }
+        Ok(remaining)
+    }
+
+    /// Moves `amount` items of the stack into a new stack with the same type and properties, and returns its id.
+    /// At least one item has to stay in the original stack.
+    pub fn split(&mut self, id: ItemId, amount: u64) -> Result<ItemId, SiminvError> {
+        let item = self.items.get(&id).ok_or(SiminvError::UnknownItemId(id))?;
+        if amount == 0 || amount >= item.stack_size {
+            return Err(SiminvError::InvalidSplitAmount { item: id, amount })
+        }
+        let split = Item {
+            stack_size: amount,
+            ..item.clone()
+        };
+
+        self.touch(id);
+        self.items.get_mut(&id).ok_or(SiminvError::UnknownItemId(id))?.stack_size -= amount;
+        let split_id = self.item_ids.next_id();
+        self.touch(split_id);
+        self.items.insert(split_id, split);
+        Ok(split_id)
+    }
+
+    /// Returns items that are not referenced by any of the holders.
+    pub fn find_orphans(&self, holders: &[&dyn ItemHolder]) -> Vec<ItemId> {
+        let held = holders.iter()
+            .flat_map(|holder| holder.held_items())
+            .collect::<HashSet<_>>();
+
+        self.items
+            .keys()
+            .filter(|id| !held.contains(*id))
+            .copied()
+            .collect()
+    }
+
+    /// Removes items that are not referenced by any of the holders and returns their ids.
+    pub fn collect_orphans(&mut self, holders: &[&dyn ItemHolder]) -> Vec<ItemId> {
+        let orphans = self.find_orphans(holders);
+        for id in &orphans {
+            self.touch(*id);
+            self.items.remove(id);
+        }
+        orphans
+    }
+
+    pub fn get_properties_mut(&mut self, id: ItemId) -> Option<&mut ItemProperties> {
+        self.touch(id);
+        self.items.get_mut(&id).map(|item| &mut item.properties)
+    }
+
+    pub fn get_item_meta<'a>(&'a self, id: ItemId, item_types: &'a ItemTypes) -> Option<ItemMeta<'a>> {
         self.get_item(id)
-            .and_then(|item| self.get_item_type_with_type_name(&item.type_name)
+            .and_then(|item| item_types.get_item_type_with_type_name(&item.type_name)
                 .map(|item_type| ItemMeta {
                     type_name: &item.type_name,
                     display_name: &item_type.display_name,
                     stack_size: item.stack_size,
                     max_stack_size: item_type.max_stack_size,
-                    tags: &item_type.tags,
+                    tags: item_types.get_tags_with_type_name(&item.type_name).unwrap_or_default(),
+                    shape: &item_type.shape,
+                    properties: &item.properties,
+                    weight: item_type.weight,
                 }))
     }
 
-    /// TODO: return result
-    pub fn merge_or_swap(&mut self, item_id: ItemId, into_id: ItemId) -> Option<(Option<ItemId>, Option<ItemId>)> {
-        // TODO: convert those to results
-        let item = self.items.get(&item_id)?;
-        let into = self.items.get(&into_id)?;
+    /// Weight of the whole stack, 0 for items of unregistered types.
+    pub fn weight(&self, id: ItemId, item_types: &ItemTypes) -> f32 {
+        self.get_item_meta(id, item_types)
+            .map_or(0., |meta| meta.weight * meta.stack_size as f32)
+    }
+
+    /// Same as `get_item_meta`, but returns the reason why the meta is not available.
+    /// Returns `StackOverflow` if the stack exceeds the max stack size of its type,
+    /// e.g. after the item types were reloaded with a lower one.
+    pub fn check_stack_size(&self, id: ItemId, item_types: &ItemTypes) -> Result<(), SiminvError> {
+        match self.get_item_meta(id, item_types) {
+            Some(meta) if meta.stack_size > meta.max_stack_size => Err(SiminvError::StackOverflow { item: id, max_stack_size: meta.max_stack_size }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn try_get_item_meta<'a>(&'a self, id: ItemId, item_types: &'a ItemTypes) -> Result<ItemMeta<'a>, SiminvError> {
+        let item = self.get_item(id).ok_or(SiminvError::UnknownItemId(id))?;
+        self.get_item_meta(id, item_types)
+            .ok_or_else(|| SiminvError::UnknownTypeName(item.type_name.clone()))
+    }
+
+    /// Returns items whose type_name is not registered in `item_types`, together with that type_name.
+    pub fn unknown_item_types(&self, item_types: &ItemTypes) -> Vec<(ItemId, String)> {
+        self.items
+            .iter()
+            .filter(|(_, item)| item_types.get_item_type_with_type_name(&item.type_name).is_none())
+            .map(|(id, item)| (*id, item.type_name.clone()))
+            .collect()
+    }
+
+    // remembers the state of the item before the first change
+    fn touch(&mut self, id: ItemId) {
+        self.journal.entry(id).or_insert_with(|| self.items.get(&id).cloned());
+    }
+
+    pub(crate) fn take_journal(&mut self) -> HashMap<ItemId, Option<Item>> {
+        core::mem::take(&mut self.journal)
+    }
+
+    // starts a new journal for a transaction
+    pub(crate) fn checkpoint(&mut self) -> ItemsCheckpoint {
+        ItemsCheckpoint {
+            journal: core::mem::take(&mut self.journal),
+            next_id: self.item_ids.current_id,
+        }
+    }
+
+    // keeps the changes made since the checkpoint
+    pub(crate) fn commit(&mut self, checkpoint: ItemsCheckpoint) {
+        let journal = core::mem::replace(&mut self.journal, checkpoint.journal);
+        for (id, old) in journal {
+            self.journal.entry(id).or_insert(old);
+        }
+    }
+
+    // undoes the changes made since the checkpoint with the journal
+    pub(crate) fn rollback(&mut self, checkpoint: ItemsCheckpoint) {
+        let journal = core::mem::replace(&mut self.journal, checkpoint.journal);
+        for (id, old) in journal {
+            self.restore(id, old);
+        }
+        self.item_ids.current_id = checkpoint.next_id;
+    }
+
+    // sets the state of the item without recording it in the journal
+    pub(crate) fn restore(&mut self, id: ItemId, item: Option<Item>) {
+        match item {
+            Some(item) => self.items.insert(id, item),
+            None => self.items.remove(&id),
+        };
+    }
+
+    /// Returns true if `merge_or_swap` would move at least a part of the stack into the other stack,
+    /// instead of swapping them.
+    pub fn can_merge(&self, item_id: ItemId, into_id: ItemId, item_types: &ItemTypes) -> bool {
+        let (Some(item), Some(into)) = (self.items.get(&item_id), self.items.get(&into_id)) else { return false };
+        item_id != into_id
+            && item.type_name == into.type_name
+            && item_types.merge_compatibility().0(&item.properties, &into.properties)
+            && item_types.get_item_type_with_type_name(&item.type_name)
+                .is_some_and(|item_type| item_type.max_stack_size > 1 && into.stack_size < item_type.max_stack_size)
+    }
+
+    /// Merges the item into the other item of the same type, or swaps them.
+    /// Returns new items for both positions, `None` if the item has been fully merged.
+    /// Stacks with different properties are merged according to `ItemTypes::merge_compatibility`.
+    pub fn merge_or_swap(&mut self, item_id: ItemId, into_id: ItemId, item_types: &ItemTypes) -> Result<(Option<ItemId>, Option<ItemId>), SiminvError> {
+        self.merge_or_swap_with(item_id, into_id, item_types, item_types.merge_compatibility().0)
+    }
+
+    /// Same as `merge_or_swap`, but stacks with different properties are merged
+    /// only if `compatible` returns true.
+    pub fn merge_or_swap_with(
+        &mut self,
+        item_id: ItemId,
+        into_id: ItemId,
+        item_types: &ItemTypes,
+        compatible: impl Fn(&ItemProperties, &ItemProperties) -> bool,
+    ) -> Result<(Option<ItemId>, Option<ItemId>), SiminvError> {
+        let item = self.items.get(&item_id).ok_or(SiminvError::UnknownItemId(item_id))?;
+        let into = self.items.get(&into_id).ok_or(SiminvError::UnknownItemId(into_id))?;
 
         // if they are of different types, just swap them
         if item.type_name != into.type_name {
-            return Some((Some(into_id), Some(item_id)));
+            return Ok((Some(into_id), Some(item_id)));
+        }
+
+        // if their properties are not compatible, swap them as well
+        if !compatible(&item.properties, &into.properties) {
+            return Ok((Some(into_id), Some(item_id)));
         }
 
         // if they are the same type, check the max stack size
-        let item_type = self.get_item_type_with_type_name(&item.type_name)?;
+        let item_type = item_types.get_item_type_with_type_name(&item.type_name)
+            .ok_or_else(|| SiminvError::UnknownTypeName(item.type_name.clone()))?;
         // if the max stack size is 1, just swap them
         if item_type.max_stack_size == 1 {
-            return Some((Some(into_id), Some(item_id)));
+            return Ok((Some(into_id), Some(item_id)));
         }
 
         let max = item_type.max_stack_size;
+        // the rest of the merge could end up above the max stack size as well
+        for (id, stack) in [(item_id, item), (into_id, into)] {
+            if stack.stack_size > max {
+                return Err(SiminvError::StackOverflow { item: id, max_stack_size: max })
+            }
+        }
         let item_count = item.stack_size + into.stack_size;
+        self.touch(item_id);
+        self.touch(into_id);
 
         if item_count > max {
             //  into has now max stack
             //  item has the rest
-            self.items.get_mut(&into_id)?.stack_size = max;
-            self.items.get_mut(&item_id)?.stack_size = item_count - max;
-            Some((Some(item_id), Some(into_id)))
+            self.items.get_mut(&into_id).ok_or(SiminvError::UnknownItemId(into_id))?.stack_size = max;
+            self.items.get_mut(&item_id).ok_or(SiminvError::UnknownItemId(item_id))?.stack_size = item_count - max;
+            Ok((Some(item_id), Some(into_id)))
         } else if item_count == item_type.max_stack_size {
             // into has now max stack
             // item has nothing
-            self.items.get_mut(&into_id)?.stack_size = max;
+            self.items.get_mut(&into_id).ok_or(SiminvError::UnknownItemId(into_id))?.stack_size = max;
             self.items.remove(&item_id);
-            Some((None, Some(into_id)))
+            Ok((None, Some(into_id)))
         } else {
             // into has now item_count items
             // item has nothing
-            self.items.get_mut(&into_id)?.stack_size = item_count;
+            self.items.get_mut(&into_id).ok_or(SiminvError::UnknownItemId(into_id))?.stack_size = item_count;
             self.items.remove(&item_id);
-            Some((None, Some(into_id)))
+            Ok((None, Some(into_id)))
         }
     }
 }
@@ -184,10 +842,180 @@ mod tests {
 
     #[test]
     fn test_items() {
+        let mut item_types = ItemTypes::default();
+        let mut items = Items::default();
+        item_types.register_item_type(ItemType {
+            type_name: "stones".into(),
+            display_name: "Stones".into(),
+            max_stack_size: 20,
+            ..default()
+        });
+
+        // counts exceeding max stack size are split
+        let stones = items.add_items("stones", 45, &item_types).unwrap();
+        let stack_sizes = stones.iter()
+            .map(|id| items.get_item(*id).unwrap().stack_size)
+            .collect::<Vec<_>>();
+        assert_eq!(stack_sizes, vec![20, 20, 5]);
+
+        // unknown types are rejected in strict mode
+        assert_eq!(items.add_item("gloves", &item_types), Err(SiminvError::UnknownTypeName("gloves".into())));
+
+        // and accepted in lenient mode
+        item_types.set_validation(ItemValidation::Lenient);
+        let gloves = items.add_items("gloves", 45, &item_types).unwrap();
+        assert_eq!(gloves.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_items() {
+        let item_types = ItemTypes::with_validation(ItemValidation::Lenient);
         let mut items = Items::default();
-        items.add_item("sword");
-        items.add_item("gloves");
+        let sword = items.add_item("sword", &item_types).unwrap();
+        let stones = items.add_items("stones", 10, &item_types).unwrap()[0];
+        let lost = items.add_item("bow", &item_types).unwrap();
+
+        assert_eq!(items.consume(stones, 4), Ok(6));
+        assert_eq!(items.consume(stones, 7), Err(SiminvError::NotEnoughItems { item: stones, available: 6 }));
+        assert_eq!(items.consume(stones, 6), Ok(0));
+        assert!(items.get_item(stones).is_none());
+
+        let held = vec![sword];
+        assert_eq!(items.find_orphans(&[&held]), vec![lost]);
+        assert_eq!(items.collect_orphans(&[&held]), vec![lost]);
+        assert!(items.find_orphans(&[&held]).is_empty());
+
+        assert!(items.remove_item(sword).is_ok());
+        assert_eq!(items.remove_item(sword).unwrap_err(), SiminvError::UnknownItemId(sword));
     }
-}
 
+    #[test]
+    fn test_update_item_type() {
+        let mut item_types = ItemTypes::default();
+        let mut items = Items::default();
+        let stones = ItemType {
+            type_name: "stones".into(),
+            display_name: "Stones".into(),
+            max_stack_size: 20,
+            ..default()
+        };
+        let id = item_types.register_or_update_item_type(stones.clone());
+        let updated = item_types.register_or_update_item_type(ItemType {
+            display_name: "Pebbles".into(),
+            max_stack_size: 30,
+            ..stones
+        });
+        assert_eq!(id, updated);
+
+        let pebbles = items.add_item("stones", &item_types).unwrap();
+        let meta = items.get_item_meta(pebbles, &item_types).unwrap();
+        assert_eq!(meta.display_name, "Pebbles");
+        assert_eq!(meta.max_stack_size, 30);
+    }
+
+    #[test]
+    fn test_merge_with_properties() {
+        let mut item_types = ItemTypes::default();
+        let mut items = Items::default();
+        item_types.register_item_type(ItemType {
+            type_name: "potion".into(),
+            display_name: "Potion".into(),
+            max_stack_size: 10,
+            ..default()
+        });
+
+        let fresh = ItemProperties::default().with("charges", 3i64);
+        let used = ItemProperties::default().with("charges", 1i64);
+        let a = items.add_items_with_properties("potion", 2, fresh.clone(), &item_types).unwrap()[0];
+        let b = items.add_items_with_properties("potion", 2, used, &item_types).unwrap()[0];
+        let c = items.add_items_with_properties("potion", 2, fresh, &item_types).unwrap()[0];
+
+        // different properties are swapped
+        assert_eq!(items.merge_or_swap(a, b, &item_types), Ok((Some(b), Some(a))));
+        // equal properties are merged
+        assert_eq!(items.merge_or_swap(c, a, &item_types), Ok((None, Some(a))));
+        assert_eq!(items.get_item_meta(a, &item_types).unwrap().stack_size, 4);
+        // user-supplied comparison allows merging
+        assert_eq!(items.merge_or_swap_with(b, a, &item_types, |_, _| true), Ok((None, Some(a))));
+        assert_eq!(items.get_item_meta(a, &item_types).unwrap().properties.get_int("charges"), Some(3));
+    }
+
+    #[test]
+    fn test_unknown_item_types() {
+        let mut item_types = ItemTypes::with_validation(ItemValidation::Lenient);
+        let mut items = Items::default();
+        item_types.register_item_type(ItemType {
+            type_name: "sword".into(),
+            display_name: "Sword".into(),
+            max_stack_size: 1,
+            ..default()
+        });
+
+        items.add_item("sword", &item_types).unwrap();
+        let gloves = items.add_item("gloves", &item_types).unwrap();
+        assert_eq!(items.unknown_item_types(&item_types), vec![(gloves, "gloves".to_string())]);
+    }
 
+    #[test]
+    fn test_item_shape() {
+        assert_eq!(ItemShape::Single.size(), UVec2::ONE);
+        assert_eq!(ItemShape::rect(2, 3).size(), UVec2::new(2, 3));
+        assert_eq!(ItemShape::rect(2, 3).cells().len(), 6);
+
+        // anchor is always occupied
+        let shape = ItemShape::Cells(vec![UVec2::new(1, 0), UVec2::new(1, 1)]);
+        assert_eq!(shape.cells().len(), 3);
+        assert_eq!(shape.size(), UVec2::new(2, 2));
+        assert_eq!(shape.cells_at(UVec2::new(u32::MAX, 0)), None);
+    }
+
+    #[test]
+    fn test_tag_rules() {
+        let tags = |names: &[&str]| names.iter().map(|name| Tag(name.to_string())).collect::<Vec<_>>();
+        let jewelry = TagRule::AnyOf(tags(&["ring", "amulet"]));
+        let one_handed = TagRule::All(vec![
+            TagRule::AllOf(tags(&["weapon", "one-handed"])),
+            TagRule::NoneOf(tags(&["quest"])),
+        ]);
+
+        assert!(jewelry.matches(&tags(&["amulet"])));
+        assert!(!jewelry.matches(&tags(&["weapon"])));
+        assert!(one_handed.matches(&tags(&["one-handed", "weapon"])));
+        assert!(!one_handed.matches(&tags(&["weapon"])));
+        assert!(!one_handed.matches(&tags(&["weapon", "one-handed", "quest"])));
+
+        assert_eq!(jewelry.to_string(), "ring | amulet");
+        assert_eq!(one_handed.to_string(), "weapon & one-handed & !quest");
+        assert_eq!(TagRule::from(Tag("helmet".into())).to_string(), "helmet");
+
+        let parsed: TagRule = bevy::asset::ron::from_str(r#"All([AnyOf([Tag("ring"), Tag("amulet")]), NoneOf([Tag("quest")])])"#).unwrap();
+        assert_eq!(parsed.to_string(), "(ring | amulet) & !quest");
+    }
+
+    #[test]
+    fn test_tag_hierarchy() {
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "longsword".into(),
+            display_name: "Longsword".into(),
+            max_stack_size: 1,
+            tags: vec![Tag("weapon/sword/longsword".into())],
+            ..default()
+        });
+        let mut items = Items::default();
+        let longsword = items.add_item("longsword", &item_types).unwrap();
+
+        let tags = items.get_item_meta(longsword, &item_types).unwrap().tags;
+        assert!(TagRule::from(Tag("weapon".into())).matches(tags));
+        assert!(TagRule::from(Tag("weapon/sword".into())).matches(tags));
+        assert!(!TagRule::from(Tag("sword".into())).matches(tags));
+        assert!(TagRule::NoneOf(vec![Tag("weapon/axe".into())]).matches(tags));
+
+        // parents added later apply to already registered types, cycles do not loop forever
+        item_types.add_tag_parent(&Tag("weapon/sword".into()), &Tag("two-handed".into()));
+        item_types.add_tag_parent(&Tag("two-handed".into()), &Tag("weapon/sword".into()));
+        let tags = items.get_item_meta(longsword, &item_types).unwrap().tags;
+        assert!(TagRule::from(Tag("two-handed".into())).matches(tags));
+        assert_eq!(tags.len(), 4);
+    }
+}
@@ -0,0 +1,188 @@
+use bevy::{asset::{io::Reader, ron, AssetLoader, LoadContext}, platform::collections::HashMap, prelude::*};
+
+use crate::{event::{SlotEvent, SlotUpdate, TriggerSlotEvent}, item::{ItemId, ItemType, ItemTypes, Items}, slot::Slot};
+
+/// List of item types loaded from `.items.ron` files.
+///
+/// Item types are registered in `ItemTypes` as soon as the asset is loaded. When the file
+/// changes (requires bevy `file_watcher` feature), existing item types are updated,
+/// item types removed from the file are unregistered and all slots are refreshed.
+#[derive(Asset, TypePath, Debug)]
+pub struct ItemTypeDatabase {
+    pub item_types: Vec<ItemType>,
+}
+
+/// Sent after item types are loaded, for every item whose type_name is not registered,
+/// e.g. because the type was removed from the game, but the item is still in a save file.
+#[derive(Message, Debug, Clone)]
+pub struct UnknownItemType {
+    pub item: ItemId,
+    pub type_name: String,
+}
+
+#[derive(Debug)]
+pub enum ItemTypeDatabaseLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    InvalidWeight { type_name: String, weight: f32 },
+}
+
+impl core::fmt::Display for ItemTypeDatabaseLoaderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ItemTypeDatabaseLoaderError::Io(err) => write!(f, "could not read item types: {err}"),
+            ItemTypeDatabaseLoaderError::Ron(err) => write!(f, "could not parse item types: {err}"),
+            ItemTypeDatabaseLoaderError::InvalidWeight { type_name, weight } => write!(f, "item type {type_name} has invalid weight {weight}"),
+        }
+    }
+}
+
+impl std::error::Error for ItemTypeDatabaseLoaderError {}
+
+impl From<std::io::Error> for ItemTypeDatabaseLoaderError {
+    fn from(value: std::io::Error) -> Self {
+        ItemTypeDatabaseLoaderError::Io(value)
+    }
+}
+
+impl From<ron::error::SpannedError> for ItemTypeDatabaseLoaderError {
+    fn from(value: ron::error::SpannedError) -> Self {
+        ItemTypeDatabaseLoaderError::Ron(value)
+    }
+}
+
+#[derive(Default)]
+pub struct ItemTypeDatabaseLoader;
+
+impl AssetLoader for ItemTypeDatabaseLoader {
+    type Asset = ItemTypeDatabase;
+    type Settings = ();
+    type Error = ItemTypeDatabaseLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        parse_item_types(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["items.ron"]
+    }
+}
+
+fn parse_item_types(bytes: &[u8]) -> Result<ItemTypeDatabase, ItemTypeDatabaseLoaderError> {
+    let item_types = ron::de::from_bytes::<Vec<ItemType>>(bytes)?;
+    // negative weights would offset the weight limits of collections
+    if let Some(invalid) = item_types.iter().find(|item_type| !item_type.has_valid_weight()) {
+        return Err(ItemTypeDatabaseLoaderError::InvalidWeight { type_name: invalid.type_name.clone(), weight: invalid.weight })
+    }
+    Ok(ItemTypeDatabase { item_types })
+}
+
+// registers the item types of the database and unregisters the types it had before, but not anymore
+fn reload_item_types(item_types: &mut ItemTypes, previous: &[String], database: &ItemTypeDatabase) -> Vec<String> {
+    let type_names: Vec<String> = database.item_types.iter()
+        .map(|item_type| item_type.type_name.clone())
+        .collect();
+    for type_name in previous.iter().filter(|type_name| !type_names.contains(type_name)) {
+        item_types.unregister_item_type(type_name);
+    }
+    item_types.register_or_update_item_types(database.item_types.iter().cloned());
+    type_names
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn apply_item_type_databases(
+    mut events: MessageReader<AssetEvent<ItemTypeDatabase>>,
+    databases: Res<Assets<ItemTypeDatabase>>,
+    // type names registered by every database, so types of other databases are not removed on reload
+    mut registered: Local<HashMap<AssetId<ItemTypeDatabase>, Vec<String>>>,
+    mut item_types: ResMut<ItemTypes>,
+    items: Res<Items>,
+    mut unknown: MessageWriter<UnknownItemType>,
+    mut commands: Commands,
+    query: Query<Entity, With<Slot>>,
+) {
+    let mut updated = false;
+    for event in events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else { continue };
+        let Some(database) = databases.get(*id) else { continue };
+        let previous = registered.remove(id).unwrap_or_default();
+        let type_names = reload_item_types(&mut item_types, &previous, database);
+        registered.insert(*id, type_names);
+        updated = true;
+    }
+
+    if updated {
+        // reconcile persisted items with the loaded item types
+        for (item, type_name) in items.unknown_item_types(&item_types) {
+            warn!("item {item:?} has unknown type_name: {type_name}");
+            unknown.write(UnknownItemType { item, type_name });
+        }
+
+        // display names, stack sizes, tags and shapes may have changed
+        for entity in query {
+            commands.trigger_slot_event(SlotEvent::new(entity, SlotUpdate));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_item_types() {
+        let database = parse_item_types(br#"[
+            (type_name: "stones", display_name: "Stones", max_stack_size: 20, tags: [], weight: 0.5),
+        ]"#).unwrap();
+        assert_eq!(database.item_types[0].weight, 0.5);
+
+        let invalid = parse_item_types(br#"[
+            (type_name: "balloon", display_name: "Balloon", max_stack_size: 1, tags: [], weight: -1.0),
+        ]"#);
+        assert!(matches!(invalid, Err(ItemTypeDatabaseLoaderError::InvalidWeight { weight: -1.0, .. })));
+
+        // programmatically registered types are treated as weightless
+        let mut item_types = ItemTypes::default();
+        let mut stones = database.item_types[0].clone();
+        stones.weight = f32::NAN;
+        item_types.register_item_type(stones);
+        assert_eq!(item_types.get_item_type_with_type_name("stones").unwrap().weight, 0.);
+    }
+
+    #[test]
+    fn test_reload_item_types() {
+        let database = parse_item_types(br#"[
+            (type_name: "stones", display_name: "Stones", max_stack_size: 20, tags: [], weight: 0.5),
+            (type_name: "sword", display_name: "Sword", max_stack_size: 1, tags: [], weight: 3.0),
+        ]"#).unwrap();
+        let mut item_types = ItemTypes::default();
+        item_types.register_item_type(ItemType {
+            type_name: "apple".to_string(),
+            ..default()
+        });
+        let registered = reload_item_types(&mut item_types, &[], &database);
+        assert_eq!(registered, ["stones", "sword"]);
+
+        let mut items = Items::default();
+        let sword = items.add_item("sword", &item_types).unwrap();
+
+        // the sword is removed from the file
+        let database = parse_item_types(br#"[
+            (type_name: "stones", display_name: "Stones", max_stack_size: 50, tags: [], weight: 0.5),
+        ]"#).unwrap();
+        let registered = reload_item_types(&mut item_types, &registered, &database);
+        assert_eq!(registered, ["stones"]);
+        assert_eq!(item_types.get_item_type_with_type_name("stones").unwrap().max_stack_size, 50);
+        assert!(item_types.get_item_type_with_type_name("sword").is_none());
+        // types of other databases are kept
+        assert!(item_types.get_item_type_with_type_name("apple").is_some());
+        assert_eq!(items.unknown_item_types(&item_types), [(sword, "sword".to_string())]);
+    }
+}
@@ -1,25 +1,37 @@
 mod auto_move;
+mod error;
 mod inventory;
 mod slot;
 mod slot_background;
 mod item;
+mod item_type_database;
 mod grid;
 mod plugin;
 mod event;
 mod slot_updater;
+mod pointer;
+mod move_validator;
+mod history;
+mod context_menu;
 pub mod input;
 pub mod simple_renderer;
 
 pub mod prelude {
     pub use crate::{
         auto_move::*,
+        error::*,
         inventory::*,
         slot::*,
         slot_background::*,
         item::*,
+        item_type_database::*,
         grid::*,
         plugin::*,
         event::*,
+        pointer::*,
+        move_validator::*,
+        history::*,
+        context_menu::*,
     };
 }
 
@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+
+use crate::{error::SiminvError, inventory::Inventory, item::{ItemId, ItemTypes, Items}, slot::InventoryHandle};
+
+/// Item about to be moved, checked by `MoveValidator`s before anything is changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemMove {
+    pub item: ItemId,
+    /// Inventory index the item is taken from, `None` for slots outside of the inventory
+    /// and for items added to the inventory.
+    pub from: Option<InventoryHandle>,
+    /// Inventory index the item is moved to, `None` for slots outside of the inventory.
+    pub to: Option<InventoryHandle>,
+    /// Item at the target, that the moved item is merged or swapped with.
+    pub replaced: Option<ItemId>,
+}
+
+/// Game rule that can veto item moves, e.g. "cursed items can't be unequipped",
+/// or "quest items can't be put into the stash".
+///
+/// Validators are registered with `MoveValidators::add` and run by drag and drop,
+/// auto-move and the `Inventory` methods.
+pub trait MoveValidator: Send + Sync + 'static {
+    /// Returns the reason why the move is not allowed.
+    fn validate(&self, item_move: &ItemMove, inventory: &Inventory, items: &Items, item_types: &ItemTypes) -> Result<(), String>;
+}
+
+impl<F> MoveValidator for F
+where
+    F: Fn(&ItemMove, &Inventory, &Items, &ItemTypes) -> Result<(), String> + Send + Sync + 'static,
+{
+    fn validate(&self, item_move: &ItemMove, inventory: &Inventory, items: &Items, item_types: &ItemTypes) -> Result<(), String> {
+        self(item_move, inventory, items, item_types)
+    }
+}
+
+/// Game rules vetoing item moves. Kept apart from `ItemTypes`, which only holds the item type data.
+#[derive(Resource, Default)]
+pub struct MoveValidators {
+    validators: Vec<Box<dyn MoveValidator>>,
+}
+
+impl MoveValidators {
+    /// Registers a game rule that can veto item moves.
+    pub fn add(&mut self, validator: impl MoveValidator) {
+        self.validators.push(Box::new(validator));
+    }
+
+    /// Returns an error if any of the validators vetoes the move, or the move of the replaced item
+    /// the other way, unless the stacks get merged.
+    pub fn check(&self, item_move: &ItemMove, inventory: &Inventory, items: &Items, item_types: &ItemTypes) -> Result<(), SiminvError> {
+        if self.validators.is_empty() {
+            return Ok(())
+        }
+
+        let swapped = item_move.replaced
+            .filter(|replaced| !items.can_merge(item_move.item, *replaced, item_types))
+            .map(|replaced| ItemMove {
+                item: replaced,
+                from: item_move.to.clone(),
+                to: item_move.from.clone(),
+                replaced: Some(item_move.item),
+            });
+
+        for item_move in core::iter::once(item_move).chain(swapped.as_ref()) {
+            for validator in &self.validators {
+                validator.validate(item_move, inventory, items, item_types)
+                    .map_err(|reason| SiminvError::MoveVetoed { item: item_move.item, reason })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use crate::{auto_move::OnlyEmpty, item::{ItemType, ItemValidation}};
+    use super::*;
+
+    #[test]
+    fn test_move_validators() {
+        let mut item_types = ItemTypes::with_validation(ItemValidation::Lenient);
+        item_types.register_item_type(ItemType {
+            type_name: "cursed_ring".into(),
+            display_name: "Cursed Ring".into(),
+            max_stack_size: 1,
+            ..default()
+        });
+        // cursed items can't leave the equipment
+        let mut validators = MoveValidators::default();
+        validators.add(|item_move: &ItemMove, _: &Inventory, items: &Items, _: &ItemTypes| {
+            let cursed = items.get_item(item_move.item).is_some_and(|item| item.type_name == "cursed_ring");
+            let unequipped = item_move.from.as_ref().is_some_and(|from| from.collection == "equipment");
+            if cursed && unequipped {
+                return Err("the ring is cursed".into())
+            }
+            Ok(())
+        });
+
+        let mut items = Items::default();
+        let ring = items.add_item("cursed_ring", &item_types).unwrap();
+        let sword = items.add_item("sword", &item_types).unwrap();
+        let mut inventory = Inventory::default();
+        inventory.add("equipment", ring, &items, &item_types, &validators).unwrap();
+        inventory.add("backpack", sword, &items, &item_types, &validators).unwrap();
+
+        let vetoed = Err(SiminvError::MoveVetoed { item: ring, reason: "the ring is cursed".into() });
+        assert_eq!(inventory.transfer("equipment", UVec2::ZERO, "backpack", UVec2::X, &mut items, &item_types, &validators), vetoed);
+        assert_eq!(inventory.quick_move("equipment", UVec2::ZERO, "backpack", &OnlyEmpty, &mut items, &item_types, &validators), Err(SiminvError::NoMatchingSlot(ring)));
+        // swapping would unequip the ring as well
+        assert_eq!(inventory.transfer("backpack", UVec2::ZERO, "equipment", UVec2::ZERO, &mut items, &item_types, &validators), vetoed);
+        assert_eq!(inventory.get("equipment", &UVec2::ZERO), Some(&ring));
+
+        // moving within the equipment is not allowed either
+        assert!(inventory.transfer("equipment", UVec2::ZERO, "equipment", UVec2::X, &mut items, &item_types, &validators).is_err());
+    }
+}
@@ -1,29 +1,63 @@
 use bevy::prelude::*;
 
-use crate::{inventory::{self}, slot, slot_background, slot_updater, input};
+use crate::{context_menu, grid, history, inventory::{self}, item, item_type_database, move_validator, slot, slot_background, slot_updater, input, pointer};
 
-pub struct SiminvPlugin;
+/// Registers the inventory resources, observers and systems.
+///
+/// The plugin is configured with its fields, so add it with `SiminvPlugin::default()`
+/// or e.g. `SiminvPlugin { ask_split_amount: true, ..default() }`, instead of `SiminvPlugin`.
+#[derive(Default)]
+pub struct SiminvPlugin {
+    /// How items of unregistered types are treated.
+    /// Ignored if `ItemTypes` is inserted before the plugin, use `ItemTypes::with_validation` then.
+    pub item_validation: item::ItemValidation,
+    /// Ctrl-drags ask for the split amount, e.g. with the dialog of the simple renderer.
+    pub ask_split_amount: bool,
+}
 
 impl Plugin for SiminvPlugin {
     fn build(&self, app: &mut App) {
+        // item types inserted by the app keep their validation mode
+        if !app.world().contains_resource::<item::ItemTypes>() {
+            app.insert_resource(item::ItemTypes::with_validation(self.item_validation));
+        }
+
         app
+            // item types loaded from .items.ron files
+            .init_asset::<item_type_database::ItemTypeDatabase>()
+            .init_asset_loader::<item_type_database::ItemTypeDatabaseLoader>()
+            .add_message::<item_type_database::UnknownItemType>()
+            // runs before state transitions, so item types are registered by the time assets are reported loaded
+            .add_systems(PreUpdate, item_type_database::apply_item_type_databases)
+
             // double click
             .init_resource::<input::double_click::DoubleClick>()
             .add_observer(input::double_click::on_click::<slot::Slot>)
             .add_systems(Update, input::double_click::update_time)
 
-            // shift click
-            .init_resource::<input::shift_click::ShiftClick>()
-            .add_observer(input::shift_click::on_click::<slot::Slot>)
-            .add_systems(Update, input::shift_click::detect_shift_press)
+            // clicks with modifier keys, shift click by default
+            .init_resource::<input::modified_click::ClickBindings>()
+            .add_observer(input::modified_click::on_click::<slot::Slot>)
+
+            // ctrl-drag and right-drag split the stack
+            .insert_resource(input::split_drag::SplitDrag::with_ask_amount(self.ask_split_amount))
+
+            // right click, opening the context menu
+            .init_resource::<context_menu::ContextMenus>()
+            .add_observer(input::right_click::on_click::<slot::Slot>)
 
             // long hover
             .init_resource::<input::hover::Hover>()
             .add_observer(input::hover::on_over::<slot_background::SlotBackground>)
             .add_observer(input::hover::on_out::<slot_background::SlotBackground>)
+            .add_observer(input::hover::on_pointer_remove)
             .add_systems(Update, input::hover::update_time)
 
-            .init_resource::<slot::Dragged>()
+            // game rules vetoing item moves
+            .init_resource::<move_validator::MoveValidators>()
+
+            .init_resource::<pointer::Pointers>()
+            .add_observer(pointer::on_pointer_remove)
             .init_resource::<slot_updater::SlotUpdater>()
             .add_observer(slot::on_add)
             .add_observer(slot::on_pointer_over)
@@ -32,12 +66,23 @@ impl Plugin for SiminvPlugin {
             .add_observer(slot::on_pointer_drag)
             .add_observer(slot::on_pointer_drag_end)
             .add_observer(slot::on_pointer_drag_drop)
+            .add_observer(slot::on_slot_split)
             .add_observer(slot_background::on_add)
             .add_observer(slot_background::on_pointer_over)
             .add_observer(slot_background::on_pointer_out)
             .add_observer(inventory::on_slot_update)
             .add_observer(slot_updater::on_slot_add)
-            .add_systems(Update, slot_updater::propagete_inventory_changes);
+            .add_observer(slot_updater::on_slot_remove)
+            .add_observer(grid::on_grid_layout_add)
+            .add_systems(Update, slot_updater::propagete_inventory_changes)
+
+            // inventory changes for gameplay systems
+            .add_message::<inventory::InventoryChanged>()
+            .add_systems(PostUpdate, inventory::write_inventory_changes)
+
+            // undo and redo, changes made in one frame are grouped together
+            .init_resource::<history::History>()
+            .add_systems(PostUpdate, history::record_history);
     }
 }
 
@@ -0,0 +1,152 @@
+use bevy::{ecs::system::SystemParam, picking::pointer::PointerId, platform::collections::HashMap, prelude::*};
+
+use crate::item::ItemId;
+
+/// Marks slots, or any of their ancestors, as owned by the entity, e.g. a player.
+/// Pointers bound to a different owner can't interact with them.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InventoryOwner(pub Entity);
+
+/// State of a single pointer (mouse, touch or a custom gamepad cursor).
+#[derive(Debug, Default)]
+pub struct PointerState {
+    /// Owner that the pointer is bound to. Unbound pointers can interact with every slot.
+    pub owner: Option<Entity>,
+    /// Item that is currently dragged by the pointer.
+    pub dragged: Option<ItemId>,
+    /// Slot the dragged item has been taken from.
+    pub dragged_from: Option<Entity>,
+    /// Part of the dragged stack that is split off when dropped, `None` if the whole stack is dragged.
+    pub split: Option<DragSplit>,
+    /// Slot background that is currently hovered by the pointer.
+    pub hovered: Option<Entity>,
+}
+
+/// Part of the dragged stack that is split off when the item is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DragSplit {
+    /// Half of the stack, taken by ctrl-drag.
+    Half,
+    /// One item, taken by right-drag.
+    One,
+}
+
+impl DragSplit {
+    pub fn amount(&self, stack_size: u64) -> u64 {
+        match self {
+            DragSplit::Half => stack_size / 2,
+            DragSplit::One => 1,
+        }
+    }
+}
+
+/// Drag and hover state tracked separately for every pointer,
+/// so multiple local players can manage inventories simultaneously.
+#[derive(Resource, Default, Debug)]
+pub struct Pointers {
+    states: HashMap<PointerId, PointerState>,
+}
+
+impl Pointers {
+    pub fn get(&self, pointer: &PointerId) -> Option<&PointerState> {
+        self.states.get(pointer)
+    }
+
+    pub fn get_mut(&mut self, pointer: PointerId) -> &mut PointerState {
+        self.states.entry(pointer).or_default()
+    }
+
+    /// Forgets the state of the pointer, including its owner.
+    /// Done by the `SiminvPlugin` when the pointer entity is despawned.
+    pub fn remove(&mut self, pointer: &PointerId) -> Option<PointerState> {
+        self.states.remove(pointer)
+    }
+
+    /// Binds the pointer to the owner. From now on, it can only interact with slots
+    /// that are not owned, or that are owned by the same owner.
+    pub fn bind_owner(&mut self, pointer: PointerId, owner: Entity) {
+        self.get_mut(pointer).owner = Some(owner);
+    }
+
+    pub fn unbind_owner(&mut self, pointer: PointerId) {
+        self.get_mut(pointer).owner = None;
+    }
+
+    /// Item dragged by the pointer.
+    pub fn dragged(&self, pointer: &PointerId) -> Option<ItemId> {
+        self.get(pointer).and_then(|state| state.dragged)
+    }
+
+    /// Returns true if the slot background is hovered by any pointer.
+    pub fn is_hovered(&self, entity: Entity) -> bool {
+        self.states.values().any(|state| state.hovered == Some(entity))
+    }
+
+    /// Returns true if the pointer can interact with slots owned by the `owner`.
+    pub fn can_interact(&self, pointer: &PointerId, owner: Option<Entity>) -> bool {
+        match (self.get(pointer).and_then(|state| state.owner), owner) {
+            (Some(pointer_owner), Some(owner)) => pointer_owner == owner,
+            _ => true,
+        }
+    }
+}
+
+// states of pointers that went away, e.g. lifted touches, would pile up otherwise
+pub(crate) fn on_pointer_remove(
+    remove: On<Remove, PointerId>,
+    query: Query<&PointerId>,
+    mut pointers: ResMut<Pointers>,
+) {
+    if let Ok(pointer) = query.get(remove.entity) {
+        pointers.remove(pointer);
+    }
+}
+
+/// Finds owners of slots by looking at the slot and all of its ancestors.
+#[derive(SystemParam)]
+pub struct Owners<'w, 's> {
+    parents: Query<'w, 's, &'static ChildOf>,
+    owners: Query<'w, 's, &'static InventoryOwner>,
+}
+
+impl<'w, 's> Owners<'w, 's> {
+    pub fn owner_of(&self, entity: Entity) -> Option<Entity> {
+        core::iter::once(entity)
+            .chain(self.parents.iter_ancestors(entity))
+            .find_map(|entity| self.owners.get(entity).ok())
+            .map(|owner| owner.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointers() {
+        let mut world = World::new();
+        let player_1 = world.spawn_empty().id();
+        let player_2 = world.spawn_empty().id();
+        let pointer_1 = PointerId::Touch(1);
+        let pointer_2 = PointerId::Touch(2);
+
+        let mut pointers = Pointers::default();
+        pointers.bind_owner(pointer_1, player_1);
+        pointers.get_mut(pointer_2).dragged = Some(ItemId::from(0));
+
+        assert!(pointers.can_interact(&pointer_1, Some(player_1)));
+        assert!(!pointers.can_interact(&pointer_1, Some(player_2)));
+        assert!(pointers.can_interact(&pointer_1, None));
+        assert!(pointers.can_interact(&pointer_2, Some(player_2)));
+        assert_eq!(pointers.dragged(&pointer_1), None);
+        assert_eq!(pointers.dragged(&pointer_2), Some(ItemId::from(0)));
+
+        // the state is forgotten with the pointer entity
+        world.insert_resource(pointers);
+        world.add_observer(on_pointer_remove);
+        let entity = world.spawn(pointer_2).id();
+        world.despawn(entity);
+        assert!(world.resource::<Pointers>().get(&pointer_2).is_none());
+        assert!(world.resource::<Pointers>().get(&pointer_1).is_some());
+    }
+}
@@ -1,8 +1,8 @@
 use std::marker::PhantomData;
 
-use bevy::{image::TRANSPARENT_IMAGE_HANDLE, prelude::*};
+use bevy::{image::TRANSPARENT_IMAGE_HANDLE, prelude::*, ui::ComputedUiRenderTargetInfo};
 
-use crate::{event::{SlotAdd, SlotBackgroundAdd, SlotBackgroundOut, SlotBackgroundOver, SlotEvent, SlotUpdate}, item::{Items, Tag}, slot::{Dragged, Slot, SlotHandle}};
+use crate::{context_menu::{ContextAction, ContextMenus}, event::{SlotAdd, SlotBackgroundAdd, SlotBackgroundOut, SlotBackgroundOver, SlotContextAction, SlotEvent, SlotRightClick, SlotSplit, SlotSplitRequest, SlotUpdate, TriggerSlotEvent}, inventory::Inventory, item::{ItemId, ItemTypes, Items}, move_validator::MoveValidators, pointer::{Owners, Pointers}, slot::{check_drop, InventoryHandle, Slot, SlotHandle}};
 
 #[derive(Debug)]
 pub enum SimpleImageHandle {
@@ -49,7 +49,33 @@ impl<T, S> Plugin for SiminvSimpleRendererPlugin<T, S> where T: SimpleRendererAs
             .add_observer(on_background_over::<T, S>)
             .add_observer(on_background_out::<T, S>)
             .add_observer(on_slot_add::<S>)
-            .add_observer(on_slot_update::<T, S>);
+            .add_observer(on_slot_update::<T, S>)
+            .add_observer(on_split_request::<S>)
+            .add_observer(on_right_click::<S>)
+            .add_systems(Update, close_context_menus)
+            .add_systems(Update, update_weight_text::<S>.run_if(resource_changed::<Inventory>.or(resource_changed::<Items>).or(resource_changed::<ItemTypes>)));
+    }
+}
+
+/// Text displaying the current and max weight of the collection, e.g. under the grid.
+#[derive(Component, Debug, Clone)]
+#[require(Text)]
+pub struct WeightText {
+    pub collection: String,
+}
+
+fn update_weight_text<S: Component>(
+    mut query: Query<(&mut Text, &WeightText), With<S>>,
+    inventory: Res<Inventory>,
+    items: Res<Items>,
+    item_types: Res<ItemTypes>,
+) {
+    for (mut text, weight_text) in &mut query {
+        let weight = inventory.weight(&weight_text.collection, &items, &item_types);
+        text.0 = match inventory.max_weight(&weight_text.collection) {
+            Some(max_weight) => format!("{weight:.1}/{max_weight:.1}"),
+            None => format!("{weight:.1}"),
+        };
     }
 }
 
@@ -78,31 +104,41 @@ fn on_background_add<T: SimpleRendererAssets, S: Component>(
         .add_child(id);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn on_background_over<T: SimpleRendererAssets, S: Component>(
     over: On<SlotEvent<SlotBackgroundOver>, S>,
     query_handle: Query<(&SlotBackgroundImageHandle, &SlotHandle)>,
     mut query_image: Query<&mut ImageNode>,
-    query_slot: Query<&Slot>,
+    query_slot: Query<(&Slot, Option<&InventoryHandle>)>,
     assets: Res<T>,
     items: Res<Items>,
-    dragged: Res<Dragged>,
+    item_types: Res<ItemTypes>,
+    inventory: Res<Inventory>,
+    pointers: Res<Pointers>,
+    owners: Owners,
+    validators: Res<MoveValidators>,
 ) {
     let Ok((image_handle, slot_handle)) = query_handle.get(over.entity) else { return };
     let Ok(mut image) = query_image.get_mut(image_handle.0) else { return };
-    match dragged.0 {
+    match pointers.dragged(&over.pointer) {
         None => {
             // nothing is dragged
             *image = assets.background_over().into();
         },
-        Some(item) => {
-            let Some(item) = items.get_item_meta(item) else { return };
+        Some(_) => {
             // TODO: throw error? crash? 
-            let Ok(slot) = query_slot.get(slot_handle.0) else { return };
+            let Ok(into) = query_slot.get(slot_handle.0) else { return };
+            let Some(from) = pointers.get(&over.pointer)
+                .and_then(|state| state.dragged_from)
+                .and_then(|from| query_slot.get(from).ok()) else { return };
 
-            if slot.matching_tag(item.tags) {
+            let can_interact = pointers.can_interact(&over.pointer, owners.owner_of(slot_handle.0));
+            // the same checks as the drop, tags, collection filter, fits, weight and game rules
+            let allowed = check_drop(from, into, &items, &item_types, &inventory, &validators).is_ok();
+            if can_interact && allowed {
                 *image = assets.background_over().into();
             } else {
-                // tags are not matching
+                // the drop would fail, or the slot belongs to someone else
                 *image = assets.background_error().into();
             }
         },
@@ -114,7 +150,13 @@ fn on_background_out<T: SimpleRendererAssets, S: Component>(
     query_handle: Query<&SlotBackgroundImageHandle>,
     mut query: Query<&mut ImageNode>,
     assets: Res<T>,
+    pointers: Res<Pointers>,
 ) {
+    // keep the highlight while other pointers are still over the slot
+    if pointers.is_hovered(out.entity) {
+        return
+    }
+
     let Ok(handle) = query_handle.get(out.entity) else { return };
     let Ok(mut image) = query.get_mut(handle.0) else { return };
     *image = assets.background().into();
@@ -130,14 +172,15 @@ fn on_slot_add<S: Component>(
     add: On<SlotEvent<SlotAdd>, S>,
     mut commands: Commands,
 ) {
+    let mut image_node = Node {
+        position_type: PositionType::Absolute,
+        ..default()
+    };
+    set_image_size(&mut image_node, UVec2::ONE);
+
     let image_id = commands.spawn((
         ImageNode::default(),
-        Node {
-            position_type: PositionType::Absolute,
-            width: percent(60),
-            height: percent(60),
-            ..default()
-        },
+        image_node,
         Pickable::IGNORE,
     )).id();
 
@@ -163,22 +206,44 @@ fn on_slot_add<S: Component>(
         .add_children(&[image_id, text_id]);
 }
 
+// sizes the item image to cover `cells` starting from the top-left cell of the slot
+// gaps between the grid cells are not taken into account
+fn set_image_size(node: &mut Node, cells: UVec2) {
+    const IMAGE_SIZE: f32 = 60.;
+    const MARGIN: f32 = (100. - IMAGE_SIZE) / 2.;
+
+    node.left = percent(MARGIN);
+    node.top = percent(MARGIN);
+    node.width = percent(IMAGE_SIZE + 100. * (cells.x - 1) as f32);
+    node.height = percent(IMAGE_SIZE + 100. * (cells.y - 1) as f32);
+}
+
 fn on_slot_update<T: SimpleRendererAssets, S: Component>(
     update: On<SlotEvent<SlotUpdate>, S>,
     query_handle: Query<(&Slot, &SlotItemImageHandle, &SlotTextHandle)>,
-    mut query_image: Query<&mut ImageNode>,
+    mut query_image: Query<(&mut ImageNode, &mut Node)>,
     mut query_text: Query<&mut Text>,
     assets: Res<T>,
     items: Res<Items>,
+    item_types: Res<ItemTypes>,
 ) {
     let Ok((slot, image_handle, text_handle)) = query_handle.get(update.entity) else { return };
-    let Ok(mut image) = query_image.get_mut(image_handle.0) else { return };
+    let Ok((mut image, mut image_node)) = query_image.get_mut(image_handle.0) else { return };
     let Ok(mut text) = query_text.get_mut(text_handle.0) else { return };
 
     match slot.item {
         Some(item_id) => {
-            let meta = items.get_item_meta(item_id).expect("to be there");
+            let Some(meta) = items.get_item_meta(item_id, &item_types) else {
+                // items of unregistered types (lenient validation) are rendered by their type_name only
+                let type_name = items.get_item(item_id).map(|item| item.type_name.as_str()).unwrap_or_default();
+                *image = assets.item(type_name).into();
+                set_image_size(&mut image_node, UVec2::ONE);
+                text.0 = "".to_owned();
+                return
+            };
             *image = assets.item(meta.type_name).into();
+            // multi-cell items span over the cells next to the slot
+            set_image_size(&mut image_node, meta.shape.size());
             
             // if max_stack_size != 1, display max number of elements
             text.0 = match meta.max_stack_size {
@@ -189,14 +254,315 @@ fn on_slot_update<T: SimpleRendererAssets, S: Component>(
         },
         None => {
             image.image = TRANSPARENT_IMAGE_HANDLE;
+            set_image_size(&mut image_node, UVec2::ONE);
             // if there's no item, maybe write a placeholder spot
-            text.0 = match slot.required_tag {
+            text.0 = match slot.required_tags {
                 None => "".to_owned(),
-                Some(Tag(ref tag)) => {
-                    format!("[{}]", tag)
+                Some(ref rule) => {
+                    format!("[{}]", rule)
                 }
             }
         }
     }
 }
 
+/// Dialog asking for the amount split off by a ctrl-drag, spawned when `SplitDrag::ask_amount` is enabled.
+#[derive(Component, Debug)]
+pub struct SplitDialog {
+    from: Entity,
+    into: Entity,
+    amount: u64,
+    max_amount: u64,
+    text: Entity,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SplitDialogAction {
+    Decrease,
+    Increase,
+    Confirm,
+    Cancel,
+}
+
+#[derive(Component)]
+struct SplitDialogButton {
+    dialog: Entity,
+    action: SplitDialogAction,
+}
+
+fn on_split_request<S: Component>(
+    request: On<SlotEvent<SlotSplitRequest>, S>,
+    mut commands: Commands,
+) {
+    let text = commands.spawn((
+        Text::new(format!("Split: {}", request.amount)),
+        TextFont {
+            font_size: 14.,
+            ..default()
+        },
+    )).id();
+    let dialog = commands.spawn((
+        SplitDialog {
+            from: request.from,
+            into: request.entity,
+            amount: request.amount,
+            max_amount: request.max_amount,
+            text,
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            align_self: AlignSelf::Center,
+            justify_self: JustifySelf::Center,
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            padding: UiRect::all(px(8)),
+            row_gap: px(6),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0., 0., 0., 0.8)),
+        GlobalZIndex(2000),
+    )).add_child(text).id();
+
+    let buttons = commands.spawn((
+        Node {
+            column_gap: px(6),
+            ..default()
+        },
+        ChildOf(dialog),
+    )).id();
+    for (label, action) in [
+        ("-", SplitDialogAction::Decrease),
+        ("+", SplitDialogAction::Increase),
+        ("Ok", SplitDialogAction::Confirm),
+        ("Cancel", SplitDialogAction::Cancel),
+    ] {
+        commands.spawn((
+            Text::new(label),
+            TextFont {
+                font_size: 14.,
+                ..default()
+            },
+            Node {
+                padding: UiRect::axes(px(6), px(2)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(1., 1., 1., 0.2)),
+            SplitDialogButton { dialog, action },
+            ChildOf(buttons),
+        )).observe(on_split_dialog_click);
+    }
+}
+
+fn on_split_dialog_click(
+    click: On<Pointer<Click>>,
+    mut commands: Commands,
+    query_button: Query<&SplitDialogButton>,
+    mut query_dialog: Query<&mut SplitDialog>,
+    mut query_text: Query<&mut Text>,
+) {
+    let Ok(button) = query_button.get(click.entity) else { return };
+    let Ok(mut dialog) = query_dialog.get_mut(button.dialog) else { return };
+
+    match button.action {
+        SplitDialogAction::Decrease => dialog.amount = dialog.amount.saturating_sub(1).max(1),
+        SplitDialogAction::Increase => dialog.amount = (dialog.amount + 1).min(dialog.max_amount),
+        SplitDialogAction::Confirm => {
+            commands.trigger_slot_event(SlotEvent::new(dialog.into, SlotSplit { from: dialog.from, amount: dialog.amount }));
+            commands.entity(button.dialog).despawn();
+            return
+        },
+        SplitDialogAction::Cancel => {
+            commands.entity(button.dialog).despawn();
+            return
+        },
+    }
+
+    let Ok(mut text) = query_text.get_mut(dialog.text) else { return };
+    text.0 = format!("Split: {}", dialog.amount);
+}
+
+// quarter of the screen the node is in
+enum ScreenPart {
+    TopLeft,
+    TopRight,
+    BottomRight,
+    BottomLeft,
+}
+
+impl ScreenPart {
+    fn corner(&self) -> Vec2 {
+        match *self {
+            ScreenPart::TopLeft => Vec2::new(-1., -1.),
+            ScreenPart::TopRight => Vec2::new(1., -1.),
+            ScreenPart::BottomRight => Vec2::new(1., 1.),
+            ScreenPart::BottomLeft => Vec2::new(-1., 1.),
+        }
+    }
+
+    fn opposite(&self) -> Self {
+        match *self {
+            ScreenPart::TopLeft => ScreenPart::BottomRight,
+            ScreenPart::BottomRight => ScreenPart::TopLeft,
+            ScreenPart::TopRight => ScreenPart::BottomLeft,
+            ScreenPart::BottomLeft => ScreenPart::TopRight,
+        }
+    }
+}
+
+fn compute_screen_part(transform: &UiGlobalTransform, target: &ComputedUiRenderTargetInfo) -> ScreenPart {
+    let center = transform.transform_point2(Vec2::splat(0.));
+    let half_size = target.physical_size() / 2;
+
+    match (center.y <= half_size.y as f32, center.x <= half_size.x as f32) {
+        (true, true) => ScreenPart::TopLeft,
+        (true, false) => ScreenPart::TopRight,
+        (false, true) => ScreenPart::BottomLeft,
+        (false, false) => ScreenPart::BottomRight,
+    }
+}
+
+/// Position of an absolute popup of `popup_size`, e.g. a tooltip or a context menu, next to the node.
+/// The popup is placed at the corner of the node facing the center of the viewport the node is rendered to,
+/// so it stays on the screen.
+pub fn popup_position(node: &ComputedNode, transform: &UiGlobalTransform, target: &ComputedUiRenderTargetInfo, popup_size: &UVec2) -> UiRect {
+    let screen_part = compute_screen_part(transform, target);
+
+    let half_size = node.size() * 0.5;
+    let corner = half_size * screen_part.opposite().corner();
+
+    let corner_screen_coords = transform.transform_point2(corner) * node.inverse_scale_factor();
+
+    match screen_part {
+        ScreenPart::TopLeft => {
+            UiRect { 
+                top: px(corner_screen_coords.y),
+                left: px(corner_screen_coords.x),
+                ..default()
+            }
+        },
+        ScreenPart::BottomLeft => {
+            UiRect {
+                top: px(corner_screen_coords.y - popup_size.y as f32),
+                left: px(corner_screen_coords.x),
+                ..default()
+            }
+        },
+        ScreenPart::TopRight => {
+            UiRect {
+                top: px(corner_screen_coords.y),
+                left: px(corner_screen_coords.x - popup_size.x as f32),
+                ..default()
+            }
+        },
+        ScreenPart::BottomRight => {
+            UiRect {
+                top: px(corner_screen_coords.y - popup_size.y as f32),
+                left: px(corner_screen_coords.x - popup_size.x as f32),
+                ..default()
+            }
+        }
+    }
+}
+
+/// Context menu of an item, spawned on right click with the actions registered in `ContextMenus`.
+#[derive(Component, Debug)]
+pub struct ContextMenu;
+
+#[derive(Component)]
+struct ContextMenuButton {
+    slot: Entity,
+    item: ItemId,
+    action: ContextAction,
+}
+
+fn on_right_click<S: Component>(
+    click: On<SlotEvent<SlotRightClick>, S>,
+    mut commands: Commands,
+    query: Query<(&Slot, &ComputedNode, &UiGlobalTransform, &ComputedUiRenderTargetInfo)>,
+    query_menu: Query<Entity, With<ContextMenu>>,
+    context_menus: Res<ContextMenus>,
+    items: Res<Items>,
+    item_types: Res<ItemTypes>,
+) {
+    const BUTTON_SIZE: UVec2 = UVec2::new(100, 24);
+
+    // only one menu is open at a time
+    for menu in &query_menu {
+        commands.entity(menu).try_despawn();
+    }
+
+    let Ok((slot, node, transform, target)) = query.get(click.entity) else { return };
+    let Some(item) = slot.item else { return };
+    let Some(meta) = items.get_item_meta(item, &item_types) else { return };
+    let actions = context_menus.actions(&meta);
+    if actions.is_empty() {
+        return
+    }
+
+    let menu_size = UVec2::new(BUTTON_SIZE.x, BUTTON_SIZE.y * actions.len() as u32);
+    let position = popup_position(node, transform, target, &menu_size);
+    let menu = commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: position.top,
+            left: position.left,
+            width: px(menu_size.x),
+            height: px(menu_size.y),
+            flex_direction: FlexDirection::Column,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0., 0., 0., 0.8)),
+        GlobalZIndex(2000),
+        ContextMenu,
+    )).id();
+
+    for action in actions {
+        commands.spawn((
+            Button,
+            Text::new(action.label()),
+            TextFont {
+                font_size: 14.,
+                ..default()
+            },
+            Node {
+                height: px(BUTTON_SIZE.y),
+                padding: UiRect::horizontal(px(6)),
+                ..default()
+            },
+            ContextMenuButton { slot: click.entity, item, action },
+            ChildOf(menu),
+        )).observe(on_context_menu_click);
+    }
+}
+
+fn on_context_menu_click(
+    click: On<Pointer<Click>>,
+    mut commands: Commands,
+    query_button: Query<(&ContextMenuButton, &ChildOf)>,
+) {
+    let Ok((button, child_of)) = query_button.get(click.entity) else { return };
+    commands.trigger_slot_event(SlotEvent::new(button.slot, SlotContextAction {
+        item: button.item,
+        action: button.action.clone(),
+    }));
+    commands.entity(child_of.parent()).try_despawn();
+}
+
+// pressing anywhere outside of the menu closes it
+fn close_context_menus(
+    mut commands: Commands,
+    mouse: Res<ButtonInput<MouseButton>>,
+    query_menu: Query<Entity, With<ContextMenu>>,
+    query_button: Query<&Interaction, With<ContextMenuButton>>,
+) {
+    if !mouse.get_just_pressed().any(|_| true) {
+        return
+    }
+    if query_button.iter().any(|interaction| *interaction != Interaction::None) {
+        return
+    }
+    for menu in &query_menu {
+        commands.entity(menu).try_despawn();
+    }
+}
+
@@ -1,17 +1,23 @@
 use bevy::prelude::*;
 
-use crate::{event::*, item::{ItemId, Items, Tag}, prelude::SlotBackground};
+use crate::{error::SiminvError, event::*, input::{modified_click::Modifiers, split_drag::SplitDrag}, inventory::{ChangeReason, Inventory}, item::{ItemHolder, ItemId, ItemTypes, Items, Tag, TagRule}, move_validator::{ItemMove, MoveValidators}, pointer::{DragSplit, Owners, Pointers}, prelude::SlotBackground};
 
 #[derive(Component, Default, Debug)]
 pub struct Slot {
     pub item: Option<ItemId>,
-    pub required_tag: Option<Tag>,
+    pub required_tags: Option<TagRule>,
+}
+
+impl ItemHolder for Slot {
+    fn held_items(&self) -> Vec<ItemId> {
+        self.item.into_iter().collect()
+    }
 }
 
 #[derive(Component)]
 pub struct SlotHandle(pub Entity);
 
-#[derive(Component, PartialEq, Hash, Clone, Eq)]
+#[derive(Component, Debug, PartialEq, Hash, Clone, Eq)]
 pub struct InventoryHandle {
     pub collection: String,
     pub index: UVec2,
@@ -25,14 +31,14 @@ impl Slot {
     pub fn with_item(item: ItemId) -> Self {
         Slot {
             item: Some(item),
-            required_tag: None,
+            required_tags: None,
         }
     }
 
-    pub fn with_required_tag(tag: Tag) -> Self {
+    pub fn with_required_tags(rule: impl Into<TagRule>) -> Self {
         Slot {
             item: None,
-            required_tag: Some(tag),
+            required_tags: Some(rule.into()),
         }
     }
 
@@ -41,21 +47,18 @@ impl Slot {
     }
 
     pub fn matching_tag(&self, tags: &[Tag]) -> bool {
-        match self.required_tag {
+        match self.required_tags {
             None => true,
-            Some(ref tag) => tags.contains(tag)
+            Some(ref rule) => rule.matches(tags)
         }
     }
 }
 
-#[derive(Resource, Default)]
-pub struct Dragged(pub Option<ItemId>);
-
 pub(crate) fn on_add(
     added: On<Add, Slot>,
     mut commands: Commands,
     mut query: Query<&ChildOf, With<Slot>>,
-    mut query_background: Query<&SlotBackground>,
+    query_background: Query<&SlotBackground>,
 ) {
     commands.entity(added.entity)
         .try_insert((
@@ -90,7 +93,7 @@ pub(crate) fn on_pointer_over(
         // do not propagate to slot background
         // it has its own observer
         over.propagate(false);
-        commands.trigger_slot_event(SlotEvent::new(over.entity, SlotOver));
+        commands.trigger_slot_event(SlotEvent::new(over.entity, SlotOver { pointer: over.pointer_id }));
     }
 }
 
@@ -103,21 +106,36 @@ pub(crate) fn on_pointer_out(
         // do not propagate to slot background
         // it has its own observer
         out.propagate(false);
-        commands.trigger_slot_event(SlotEvent::new(out.entity, SlotOut));
+        commands.trigger_slot_event(SlotEvent::new(out.entity, SlotOut { pointer: out.pointer_id }));
     }
 }
 
 pub(crate) fn on_pointer_drag_start(
     on_drag_start: On<Pointer<DragStart>>,
     mut query: Query<(&Slot, &mut GlobalZIndex), With<Slot>>,
-    mut commands: Commands,
+    mut pointers: ResMut<Pointers>,
+    owners: Owners,
+    items: Res<Items>,
+    split_drag: Res<SplitDrag>,
+    keys: Res<ButtonInput<KeyCode>>,
 ) {
+    let pointer = on_drag_start.pointer_id;
     if let Ok((slot, mut z_index)) = query.get_mut(on_drag_start.event_target()) {
+        // pointers bound to other owners can't take items from the slot
+        if !pointers.can_interact(&pointer, owners.owner_of(on_drag_start.event_target())) {
+            return
+        }
+
         // we can only drag items that have something inside
         if slot.item.is_some() {
             // we are draggin it. it should always be on the top
             z_index.0 = 1000;
-            commands.insert_resource(Dragged(slot.item));
+            let state = pointers.get_mut(pointer);
+            state.dragged = slot.item;
+            state.dragged_from = Some(on_drag_start.event_target());
+            // stacks of a single item are dragged whole
+            let stack_size = slot.item.and_then(|item| items.get_item(item)).map_or(0, |item| item.stack_size);
+            state.split = split_drag.split(on_drag_start.button, Modifiers::pressed(&keys)).filter(|_| stack_size > 1);
         }
     }
 }
@@ -126,10 +144,11 @@ pub(crate) fn on_pointer_drag(
     on_drag: On<Pointer<Drag>>,
     mut query: Query<(&Slot, &mut UiTransform), With<Slot>>,
     ui_scale: Option<Res<UiScale>>,
+    pointers: Res<Pointers>,
 ) {
     if let Ok((slot, mut transform)) = query.get_mut(on_drag.event_target()) {
-        // we can only drag items that have something inside
-        if slot.item.is_some() {
+        // we can only drag items that have something inside and were picked up by this pointer
+        if slot.item.is_some() && pointers.dragged(&on_drag.pointer_id) == slot.item {
             // adjust translation to the current screen scale
             let scale = match ui_scale {
                 Some(scale) => scale.0,
@@ -143,61 +162,271 @@ pub(crate) fn on_pointer_drag(
 pub(crate) fn on_pointer_drag_end(
     on_drag_end: On<Pointer<DragEnd>>,
     mut query: Query<(&mut UiTransform, &mut GlobalZIndex), With<Slot>>,
-    mut commands: Commands,
+    mut pointers: ResMut<Pointers>,
 ) {
     if let Ok((mut transform, mut z_index)) = query.get_mut(on_drag_end.event_target()) {
         transform.translation = Val2::ZERO;
         z_index.0 = 0;
-        commands.insert_resource(Dragged(None));
+        let state = pointers.get_mut(on_drag_end.pointer_id);
+        state.dragged = None;
+        state.dragged_from = None;
+        state.split = None;
+    }
+}
+
+// checks if items swapped between the slots fit into their new positions
+pub(crate) fn check_fits_after_swap(
+    inventory: &Inventory,
+    items: &Items,
+    item_types: &ItemTypes,
+    (from_handle, from_item): (Option<&InventoryHandle>, Option<ItemId>),
+    (into_handle, into_item): (Option<&InventoryHandle>, Option<ItemId>),
+) -> Result<(), SiminvError> {
+    let check = |handle: Option<&InventoryHandle>, item: Option<ItemId>, other: Option<&InventoryHandle>| {
+        let (Some(handle), Some(item)) = (handle, item) else { return Ok(()) };
+
+        // both swapped items are moved away, so their cells should not be considered occupied
+        let mut ignored = vec![handle.index];
+        if let Some(other) = other.filter(|other| other.collection == handle.collection) {
+            ignored.push(other.index);
+        }
+        inventory.check_fits(&handle.collection, handle.index, item, items, item_types, &ignored)?;
+
+        // the weight of the collection changes only if the items come from elsewhere
+        if other.is_none_or(|other| other.collection != handle.collection) {
+            let replaced_weight = inventory.get(&handle.collection, &handle.index).map_or(0., |replaced| items.weight(*replaced, item_types));
+            inventory.check_weight(&handle.collection, item, items.weight(item, item_types) - replaced_weight, items, item_types)?;
+        }
+        Ok(())
+    };
+
+    check(into_handle, from_item, from_handle)?;
+    check(from_handle, into_item, into_handle)
+}
+
+/// Returns the reason why dropping the item of one slot into the other would fail,
+/// checked the same way as drag and drop does, e.g. to highlight the slots under the pointer.
+pub fn check_drop(
+    (slot_from, handle_from): (&Slot, Option<&InventoryHandle>),
+    (slot_into, handle_into): (&Slot, Option<&InventoryHandle>),
+    items: &Items,
+    item_types: &ItemTypes,
+    inventory: &Inventory,
+    validators: &MoveValidators,
+) -> Result<(), SiminvError> {
+    match (handle_from, handle_into) {
+        // dropping the item back where it was taken from does nothing
+        (Some(handle_from), Some(handle_into)) if handle_from == handle_into => Ok(()),
+        (Some(handle_from), Some(handle_into)) => inventory
+            .check_transfer(&handle_from.collection, handle_from.index, &handle_into.collection, handle_into.index, items, item_types, validators),
+        _ => check_drop_into((slot_from, handle_from), (slot_into, handle_into), items, item_types, inventory, validators),
+    }
+}
+
+// checks tags, fits and game rules of a drop between slots that do not both display the inventory
+fn check_drop_into(
+    (slot_from, handle_from): (&Slot, Option<&InventoryHandle>),
+    (slot_into, handle_into): (&Slot, Option<&InventoryHandle>),
+    items: &Items,
+    item_types: &ItemTypes,
+    inventory: &Inventory,
+    validators: &MoveValidators,
+) -> Result<(), SiminvError> {
+    // nothing if the grabbed slot does not contain an item
+    let Some(from_id) = slot_from.item else { return Ok(()) };
+
+    // check if tags are matching
+    let from_item = items.try_get_item_meta(from_id, item_types)?;
+    if !slot_into.matching_tag(from_item.tags) {
+        return Err(SiminvError::TagMismatch(from_id))
+    }
+
+    if let Some(into_id) = slot_into.item {
+        let into_item = items.try_get_item_meta(into_id, item_types)?;
+        if !slot_from.matching_tag(into_item.tags) {
+            return Err(SiminvError::TagMismatch(into_id))
+        }
     }
+
+    // reject drops that would overlap other items or leave the collection
+    check_fits_after_swap(inventory, items, item_types, (handle_from, slot_from.item), (handle_into, slot_into.item))?;
+    // and drops vetoed by the game rules
+    validators.check(&ItemMove {
+        item: from_id,
+        from: handle_from.cloned(),
+        to: handle_into.cloned(),
+        replaced: slot_into.item,
+    }, inventory, items, item_types)
+}
+
+// moves the item onto empty slot, or merges or swaps it with the item in the slot
+fn drop_into(
+    (slot_from, handle_from): (&mut Slot, Option<&InventoryHandle>),
+    (slot_into, handle_into): (&mut Slot, Option<&InventoryHandle>),
+    items: &mut Items,
+    item_types: &ItemTypes,
+    inventory: &Inventory,
+    validators: &MoveValidators,
+) -> Result<(), SiminvError> {
+    // nothing if the grabbed slot does not contain an item
+    let Some(from_id) = slot_from.item else { return Ok(()) };
+    check_drop_into((slot_from, handle_from), (slot_into, handle_into), items, item_types, inventory, validators)?;
+
+    match slot_into.item {
+        // merge or swap them
+        Some(into_id) => {
+            let (new_from, new_into) = items.merge_or_swap(from_id, into_id, item_types)?;
+            slot_from.item = new_from;
+            slot_into.item = new_into;
+        },
+        // move slot item onto empty space
+        None => {
+            core::mem::swap(&mut slot_from.item, &mut slot_into.item);
+        },
+    }
+
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn on_pointer_drag_drop(
     on_drag_drop: On<Pointer<DragDrop>>,
     mut commands: Commands,
-    mut query: Query<&mut Slot>,
+    mut query: Query<(&mut Slot, Option<&InventoryHandle>)>,
     mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    mut inventory: ResMut<Inventory>,
+    pointers: Res<Pointers>,
+    owners: Owners,
+    split_drag: Res<SplitDrag>,
+    validators: Res<MoveValidators>,
 ) {
-    if let Ok([mut slot_from, mut slot_into]) = query.get_many_mut([on_drag_drop.dropped, on_drag_drop.event_target()]) {
-        match (slot_from.item, slot_into.item) {
-            // merge or swap them
-            (Some(from_id), Some(into_id)) => {
-                
-                // check if tags are matching
-                let from_item = items.get_item_meta(from_id).expect("to be there");
-                let into_item = items.get_item_meta(into_id).expect("to be there");
-                if !slot_from.matching_tag(into_item.tags) || !slot_into.matching_tag(from_item.tags) {
-                    return
-                }
-
-                
-                let (new_from, new_into) = items.merge_or_swap(from_id, into_id).expect("to be no error");
-                slot_from.item = new_from;
-                slot_into.item = new_into;
-
-                // unselect the previous item to prevent blink before pointer out triggers
-                commands.trigger_slot_event(SlotEvent::new(on_drag_drop.dropped, SlotOut));
-                commands.trigger_slot_event(SlotEvent::new(on_drag_drop.dropped, SlotUpdate));
-                commands.trigger_slot_event(SlotEvent::new(on_drag_drop.event_target(), SlotUpdate));
-            }
-            // move slot item onto empty space
-            (Some(from_id), None) => {
-                let from_item = items.get_item_meta(from_id).expect("to be there");
-                if !slot_into.matching_tag(from_item.tags) {
-                    return
-                }
-
-                core::mem::swap(&mut slot_from.item, &mut slot_into.item);
-
-                // unselect the previous item to prevent blink before pointer out triggers
-                commands.trigger_slot_event(SlotEvent::new(on_drag_drop.dropped, SlotOut));
-                commands.trigger_slot_event(SlotEvent::new(on_drag_drop.dropped, SlotUpdate));
-                commands.trigger_slot_event(SlotEvent::new(on_drag_drop.event_target(), SlotUpdate));
-            }
-            // nothing if the grabbed slot does not contain an item
-            _ => {
-            }
+    let pointer = on_drag_drop.pointer_id;
+    let Ok([(mut slot_from, handle_from), (mut slot_into, handle_into)]) = query.get_many_mut([on_drag_drop.dropped, on_drag_drop.event_target()]) else { return };
+
+    // only the items picked up by this pointer can be dropped
+    if slot_from.item.is_none() || pointers.dragged(&pointer) != slot_from.item {
+        return
+    }
+
+    // pointers bound to other owners can't put items into the slot
+    if !pointers.can_interact(&pointer, owners.owner_of(on_drag_drop.event_target())) {
+        return
+    }
+
+    // only a part of the stack has been taken
+    if let Some(split) = pointers.get(&pointer).and_then(|state| state.split) {
+        let stack_size = slot_from.item.and_then(|item| items.get_item(item)).map_or(0, |item| item.stack_size);
+        let (from, amount) = (on_drag_drop.dropped, split.amount(stack_size));
+        if split == DragSplit::Half && split_drag.ask_amount {
+            let max_amount = stack_size.saturating_sub(1);
+            commands.trigger_slot_event(SlotEvent::new(on_drag_drop.event_target(), SlotSplitRequest { from, pointer, amount, max_amount }));
+        } else {
+            commands.trigger_slot_event(SlotEvent::new(on_drag_drop.event_target(), SlotSplit { from, amount }));
         }
+        return
+    }
+
+    let result = match (handle_from, handle_into) {
+        // both slots display the inventory, so let the inventory move the items
+        (Some(handle_from), Some(handle_into)) => inventory
+            .transfer_with_reason(&handle_from.collection, handle_from.index, &handle_into.collection, handle_into.index, &mut items, &item_types, &validators, ChangeReason::Drag)
+            .map(|()| {
+                // keep the slots in sync, so SlotUpdate does not write the old items back
+                slot_from.item = inventory.get(&handle_from.collection, &handle_from.index).copied();
+                slot_into.item = inventory.get(&handle_into.collection, &handle_into.index).copied();
+            }),
+        _ => drop_into(
+            (&mut slot_from, handle_from),
+            (&mut slot_into, handle_into),
+            &mut items,
+            &item_types,
+            &inventory,
+            &validators,
+        ),
+    };
+
+    match result {
+        Ok(()) => {
+            // unselect the previous item to prevent blink before pointer out triggers
+            commands.trigger_slot_event(SlotEvent::new(on_drag_drop.dropped, SlotOut { pointer }));
+            commands.trigger_slot_event(SlotEvent::new(on_drag_drop.dropped, SlotUpdate));
+            commands.trigger_slot_event(SlotEvent::new(on_drag_drop.event_target(), SlotUpdate));
+        },
+        Err(error) => {
+            commands.trigger_slot_event(SlotEvent::new(on_drag_drop.event_target(), SlotError { error }));
+        },
+    }
+}
+
+// splits a part of the stack into the other slot, the part returns to the stack if it can't be dropped there
+fn split_into(
+    (slot_from, handle_from): (&mut Slot, Option<&InventoryHandle>),
+    (slot_into, handle_into): (&mut Slot, Option<&InventoryHandle>),
+    amount: u64,
+    items: &mut Items,
+    item_types: &ItemTypes,
+    inventory: &Inventory,
+    validators: &MoveValidators,
+) -> Result<(), SiminvError> {
+    let Some(from_id) = slot_from.item else { return Ok(()) };
+    let split_id = items.split(from_id, amount)?;
+    let mut split_slot = Slot {
+        item: Some(split_id),
+        required_tags: slot_from.required_tags.clone(),
+    };
+    let result = match slot_into.item {
+        // the split stack can only be merged, it can't be swapped with another item
+        Some(into_id) if !items.can_merge(split_id, into_id, item_types) => Err(SiminvError::NoMatchingSlot(from_id)),
+        _ => drop_into((&mut split_slot, handle_from), (slot_into, handle_into), items, item_types, inventory, validators),
+    };
+    // anything that was not dropped returns to the stack
+    if let Some(rest) = split_slot.item {
+        items.merge_or_swap(rest, from_id, item_types)?;
+    }
+    result
+}
+
+pub(crate) fn on_slot_split(
+    split: On<SlotEvent<SlotSplit>>,
+    mut commands: Commands,
+    mut query: Query<(&mut Slot, Option<&InventoryHandle>)>,
+    mut items: ResMut<Items>,
+    item_types: Res<ItemTypes>,
+    mut inventory: ResMut<Inventory>,
+    validators: Res<MoveValidators>,
+) {
+    let (from, into) = (split.from, split.entity);
+    let Ok([(mut slot_from, handle_from), (mut slot_into, handle_into)]) = query.get_many_mut([from, into]) else { return };
+
+    let result = match (handle_from, handle_into) {
+        // both slots display the inventory, so let the inventory split the stack
+        (Some(handle_from), Some(handle_into)) => inventory
+            .split(&handle_from.collection, handle_from.index, &handle_into.collection, handle_into.index, split.amount, &mut items, &item_types, &validators)
+            .map(|()| {
+                // keep the slots in sync, so SlotUpdate does not write the old items back
+                slot_from.item = inventory.get(&handle_from.collection, &handle_from.index).copied();
+                slot_into.item = inventory.get(&handle_into.collection, &handle_into.index).copied();
+            }),
+        _ => split_into(
+            (&mut slot_from, handle_from),
+            (&mut slot_into, handle_into),
+            split.amount,
+            &mut items,
+            &item_types,
+            &inventory,
+            &validators,
+        ),
+    };
+
+    match result {
+        Ok(()) => {
+            commands.trigger_slot_event(SlotEvent::new(from, SlotUpdate));
+            commands.trigger_slot_event(SlotEvent::new(into, SlotUpdate));
+        },
+        Err(error) => {
+            commands.trigger_slot_event(SlotEvent::new(into, SlotError { error }));
+        },
     }
 }
 
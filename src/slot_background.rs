@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::event::*;
+use crate::{event::*, pointer::Pointers};
 
 #[derive(Component)]
 pub struct SlotBackground;
@@ -17,10 +17,12 @@ pub(crate) fn on_pointer_over(
     over: On<Pointer<Over>>,
     mut commands: Commands,
     query: Query<&SlotBackground>,
+    mut pointers: ResMut<Pointers>,
 ) {
     // TODO: handle double trigger from Slot
     if query.get(over.entity).is_ok() {
-        commands.trigger_slot_event(SlotEvent::new(over.entity, SlotBackgroundOver));
+        pointers.get_mut(over.pointer_id).hovered = Some(over.entity);
+        commands.trigger_slot_event(SlotEvent::new(over.entity, SlotBackgroundOver { pointer: over.pointer_id }));
     }
 }
 
@@ -28,10 +30,15 @@ pub(crate) fn on_pointer_out(
     out: On<Pointer<Out>>,
     mut commands: Commands,
     query: Query<&SlotBackground>,
+    mut pointers: ResMut<Pointers>,
 ) {
     // TODO: handle double trigger from Slot
     if query.get(out.entity).is_ok() {
-        commands.trigger_slot_event(SlotEvent::new(out.entity, SlotBackgroundOut));
+        let state = pointers.get_mut(out.pointer_id);
+        if state.hovered == Some(out.entity) {
+            state.hovered = None;
+        }
+        commands.trigger_slot_event(SlotEvent::new(out.entity, SlotBackgroundOut { pointer: out.pointer_id }));
     }
 }
 
@@ -10,20 +10,33 @@ pub struct SlotUpdater {
 pub fn on_slot_add(
     added: On<SlotEvent<SlotAdd>, Slot>,
     mut query: Query<(&mut Slot, Option<&InventoryHandle>)>,
-    inventory: Res<Inventory>,
+    mut inventory: ResMut<Inventory>,
     mut updater: ResMut<SlotUpdater>,
 ) {
     let Ok((mut slot, maybe_handle)) = query.get_mut(added.entity) else { return };
 
     if let Some(handle) = maybe_handle {
         slot.item = inventory.get(&handle.collection, &handle.index).cloned();
+        // the inventory enforces slot requirements even when items are moved without the ui
+        inventory.set_required_tags(&handle.collection, handle.index, slot.required_tags.clone());
 
         // register slot
-        // TODO: unregister at some point
         updater.slot_by_inventory_handle.insert(handle.clone(), added.entity);
     }
 }
 
+pub fn on_slot_remove(
+    removed: On<Remove, InventoryHandle>,
+    query: Query<&InventoryHandle>,
+    mut updater: ResMut<SlotUpdater>,
+) {
+    let Ok(handle) = query.get(removed.entity) else { return };
+    // a respawned grid may have registered a new slot for the index already
+    if updater.slot_by_inventory_handle.get(handle) == Some(&removed.entity) {
+        updater.slot_by_inventory_handle.remove(handle);
+    }
+}
+
 pub fn propagete_inventory_changes(
     mut inventory: ResMut<Inventory>,
     mut commands: Commands,
@@ -45,3 +58,25 @@ pub fn propagete_inventory_changes(
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_remove() {
+        let mut world = World::new();
+        world.init_resource::<SlotUpdater>();
+        world.add_observer(on_slot_remove);
+        let handle = InventoryHandle { collection: "backpack".into(), index: UVec2::ZERO };
+        let old = world.spawn(handle.clone()).id();
+        let new = world.spawn(handle.clone()).id();
+        world.resource_mut::<SlotUpdater>().slot_by_inventory_handle.insert(handle.clone(), new);
+
+        // the old slot of a respawned grid does not unregister the new one
+        world.despawn(old);
+        assert_eq!(world.resource::<SlotUpdater>().slot_by_inventory_handle.get(&handle), Some(&new));
+        world.despawn(new);
+        assert!(world.resource::<SlotUpdater>().slot_by_inventory_handle.is_empty());
+    }
+}